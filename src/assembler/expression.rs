@@ -92,7 +92,7 @@ pub(crate) fn collect_expr(tokens: &Vec<Token>, start: usize, loc: Option<&Loc>)
             assert!(elems.len() == 1);
             if let Some(ExprElem::Expr(expr)) = elems.pop() { (expr, index) } else { unreachable!() }
         },
-        Token::Control('!') => Err(Error(format!("Invalid token `!` in expression, did you mean: `~` (unary not)?"), loc.cloned()))?,
+        Token::Control('!') => Err(Error("Invalid token `!` in expression, did you mean: `~` (unary not)?".to_string(), loc.cloned()))?,
         t => Err(Error(format!("Invalid token as expression `{:?}`", t), loc.cloned()))?
     })
 }
@@ -112,8 +112,11 @@ macro_rules! matching_ty_op {
     } };
 }
 
-pub(crate)fn expr_funcs_map() -> HashMap<&'static str, fn(Vec<Value>, Option<&Loc>) -> Result<Value, Error>> {
-    let mut map: HashMap<&'static str, fn(Vec<Value>, Option<&Loc>) -> Result<Value, Error>> = HashMap::new();
+/// a function which can be called in expressions
+pub(crate) type ExprFunc = fn(Vec<Value>, Option<&Loc>) -> Result<Value, Error>;
+
+pub(crate)fn expr_funcs_map() -> HashMap<&'static str, ExprFunc> {
+    let mut map: HashMap<&'static str, ExprFunc> = HashMap::new();
     macro_rules! assert_len {
         ($v: ident >= $len: expr, $loc: ident) => {
             if $v.len() < $len { Err(Error(format!("Expected at least {} args, got {}", $v.len(), $len), None))? }
@@ -155,7 +158,7 @@ pub(crate) enum Expression {
 }
 
 impl Expression {
-    pub(crate) fn eval(&self, vars: &HashMap<String, Value>, funcs: &HashMap<&'static str, fn(Vec<Value>, Option<&Loc>) -> Result<Value, Error>>, loc: Option<&Loc>) -> Result<Value, Error> {
+    pub(crate) fn eval(&self, vars: &HashMap<String, Value>, funcs: &HashMap<&'static str, ExprFunc>, loc: Option<&Loc>) -> Result<Value, Error> {
         Ok(match self {
             Expression::Variable(v) => vars.get(v).cloned().ok_or_else(|| Error(format!("Unrecognized variable `{v}`"), loc.cloned()))?,
             Expression::Value(v) => v.clone(), 
@@ -186,7 +189,7 @@ impl Expression {
                     }
                 ),
             Expression::FnCall(ident, args) => (funcs.get(ident.as_str()).ok_or_else(|| Error(format!("Unrecognized function `{ident}`"), loc.cloned()))?)(
-                args.into_iter().map(|e| e.eval(vars, funcs, loc)).collect::<Result<Vec<_>, _>>()?, loc
+                args.iter().map(|e| e.eval(vars, funcs, loc)).collect::<Result<Vec<_>, _>>()?, loc
             ).map_err(|e| e.at(loc.cloned()))?,
         })
     }
@@ -2,9 +2,9 @@ mod expression;
 
 use std::{path::{Path, PathBuf}, rc::Rc, fmt::{Display, Debug}, str::FromStr, iter::Peekable, fs::File, io::Write, collections::HashMap, hash::Hash};
 
-use crate::{machine::thread::{REG_C, REG_F, REG_S, REG_I, REG_B, instructions::instr_name_id_map}, assembler::expression::expr_funcs_map};
+use crate::{machine::thread::{REG_C, REG_F, REG_S, REG_I, REG_B, REG_H, REG_V, REG_M, REG_N, REG_P, REG_A, REG_R, instructions::instr_name_id_map}, assembler::expression::expr_funcs_map};
 
-use self::expression::{Expression, collect_expr, Op, Value};
+use self::expression::{Expression, collect_expr, Value};
 
 pub fn assemble(file_in: impl AsRef<Path>, file_out: impl AsRef<Path>) -> Result<(), Error> {
     let instrs = parse_file(file_in)?;
@@ -18,11 +18,11 @@ pub fn assemble(file_in: impl AsRef<Path>, file_out: impl AsRef<Path>) -> Result
     let mut addr = 0;
     for (loc, i) in &instrs {
         match i {
-            Instruction::Variable(ident, e) => if let Ok(v) = e.eval(&variables, &func_map, Some(&loc)) {
+            Instruction::Variable(ident, e) => if let Ok(v) = e.eval(&variables, &func_map, Some(loc)) {
                 variables.insert(ident.to_string(), v);
             },
             Instruction::Location(e) => {
-                let l = match e.eval(&variables, &func_map, Some(&loc))? {
+                let l = match e.eval(&variables, &func_map, Some(loc))? {
                     Value::UnsignedInteger(u) => u,
                     // should not be that way due to checks earlier
                     other => Err(Error(format!("@Location expects value of type unsized integer, found {other:?}"), Some(loc.clone())))?
@@ -59,9 +59,9 @@ pub fn assemble(file_in: impl AsRef<Path>, file_out: impl AsRef<Path>) -> Result
     println!("Assembling:");
     for (loc, i) in &instrs {
         match i {
-            Instruction::Variable(ident, e) => { let _ = variables.insert(ident.to_string(), e.eval(&variables, &func_map, Some(&loc))?); },
+            Instruction::Variable(ident, e) => { let _ = variables.insert(ident.to_string(), e.eval(&variables, &func_map, Some(loc))?); },
             Instruction::Location(e) => {
-                let l = match e.eval(&variables, &func_map, Some(&loc))? {
+                let l = match e.eval(&variables, &func_map, Some(loc))? {
                     Value::UnsignedInteger(u) => u,
                     // should not be a case due to checks earlier
                     _ => unreachable!()
@@ -215,10 +215,17 @@ fn instructionize(tokens: Vec<Token>, reg_aliases: &HashMap<Token, Token>, loc:
                             "S" => REG_S, 
                             "F" => REG_F, 
                             "C" => REG_C,
-                            other =>  Err(Error(format!("Invalid token for register after `%`: `{other}`, expected either base 10 unsigned integer [0..47] or one of the following: `I`, `W`, `S`, `F`, `D`, `C`"), loc.cloned()))?
+                            "H" => REG_H,
+                            "V" => REG_V,
+                            "M" => REG_M,
+                            "N" => REG_N,
+                            "P" => REG_P,
+                            "A" => REG_A,
+                            "R" => REG_R,
+                            other =>  Err(Error(format!("Invalid token for register after `%`: `{other}`, expected either base 10 unsigned integer [0..47] or one of the following: `I`, `B`, `S`, `F`, `C`, `H`, `V`, `M`, `N`, `P`, `A`, `R`"), loc.cloned()))?
                         },
                         Token::UnsignedInteger(r @ 0..=47, 10) => *r,
-                        other => Err(Error(format!("Invalid token for register after `%`: `{other:?}`, expected either base 10 unsigned integer [0..47] or one of the following: `I`, `W`, `S`, `F`, `D`, `C`"), loc.cloned()))?
+                        other => Err(Error(format!("Invalid token for register after `%`: `{other:?}`, expected either base 10 unsigned integer [0..47] or one of the following: `I`, `B`, `S`, `F`, `C`, `H`, `V`, `M`, `N`, `P`, `A`, `R`"), loc.cloned()))?
                     };
                     args.push(Arg::Register(r));
                     index += 2;
@@ -293,6 +300,7 @@ enum Data {
 }
 
 impl Data {
+    #[allow(dead_code)]
     fn ty(&self) -> &'static str {
         match self {
             Data::Ascii(_) => "ascii",
@@ -429,7 +437,7 @@ fn str_to_num_lit(n: &str, loc: Option<&Loc>) -> Result<Token, Error>{
         Err(Error(format!("Expected radix 10 for float literal `{n}`, found {radix}"), loc.cloned()))?;
     }
     let lit = if float_like {
-        f32::from_str(&num).map(|f| Token::Float(f)).map_err(|_|
+        f32::from_str(&num).map(Token::Float).map_err(|_|
             Error(format!("Invalid float literal `{n}`"), loc.cloned())
         )
     } else if !signed {
@@ -1,14 +1,32 @@
 #![feature(io_error_more)]
-#![feature(seek_stream_len)]
-#![feature(bigint_helper_methods)]
+#![feature(signed_bigint_helpers)]
 #![feature(try_blocks)]
 #![feature(macro_metavar_expr)]
 #![feature(int_roundings)]
-#![feature(let_chains)]
+#![recursion_limit = "256"]
 
 
 pub(crate) mod machine;
 mod assembler;
 
-pub use machine::Machine;
+pub use machine::{Machine, ExitStatus, ExitReason, VmError, Scheduler, Device, Bus, Console, SharedBuffer};
+pub use machine::device::{DEVICE_CONSOLE, DEVICE_RNG};
+pub use machine::device::image::Image;
+pub use machine::device::framebuffer::{Framebuffer, PixelFormat, DumpFormat, FB_REG_BASE, FB_REG_WIDTH, FB_REG_HEIGHT, FB_REG_FORMAT, FB_REG_PRESENT, FB_REG_FRAME, FB_REGS_SIZE};
+pub use machine::device::text::{TextMode, GLYPH_SIZE, TEXT_DEFAULT_ATTR, TEXT_REG_BASE, TEXT_REG_COLUMNS, TEXT_REG_ROWS, TEXT_REG_CURSOR_X, TEXT_REG_CURSOR_Y, TEXT_REG_ATTR, TEXT_REG_PUTC, TEXT_REG_SCROLL, TEXT_REG_PRESENT, TEXT_REGS_SIZE};
+pub use machine::device::block::{BlockDevice, SECTOR_SIZE, BLOCK_REG_CMD, BLOCK_REG_LBA, BLOCK_REG_COUNT, BLOCK_REG_DMA, BLOCK_REG_STATUS, BLOCK_REG_SECTORS, BLOCK_REG_IRQ, BLOCK_REG_IRQ_THREAD, BLOCK_REGS_SIZE, BLOCK_CMD_READ, BLOCK_CMD_WRITE, BLOCK_CMD_FLUSH, BLOCK_STATUS_IDLE, BLOCK_STATUS_DONE, BLOCK_STATUS_ERROR, BLOCK_IRQ_ENABLE};
+pub use machine::device::timer::{Timer, TIMER_REG_CTRL, TIMER_REG_PERIOD_LO, TIMER_REG_PERIOD_HI, TIMER_REG_REMAINING, TIMER_REG_PENDING, TIMER_REG_IRQ, TIMER_REG_IRQ_THREAD, TIMER_REGS_SIZE, TIMER_CTRL_ENABLE, TIMER_CTRL_PERIODIC, TIMER_CTRL_HOST_TIME, TIMER_IRQ_ENABLE};
+pub use machine::device::clock::{Clock, ClockSource, CLOCK_REG_MONO_LO, CLOCK_REG_MONO_HI, CLOCK_REG_WALL_LO, CLOCK_REG_WALL_HI, CLOCK_REG_WALL_NANOS, CLOCK_REG_TICK_NS, CLOCK_REGS_SIZE};
+pub use machine::device::random::{Rng, RNG_REG_VALUE, RNG_REG_SEED, RNG_REGS_SIZE};
+pub use machine::device::keyboard::{Keyboard, KBD_REG_DATA, KBD_REG_COUNT, KBD_REG_IRQ, KBD_REG_IRQ_THREAD, KBD_REG_DROPPED, KBD_REGS_SIZE, KBD_IRQ_ENABLE, KBD_KEY_MASK, KBD_MOD_SHIFT, KBD_MOD_CTRL, KBD_MOD_ALT, KBD_RELEASE,
+    KEY_BACKSPACE, KEY_TAB, KEY_ENTER, KEY_ESCAPE, KEY_UP, KEY_DOWN, KEY_LEFT, KEY_RIGHT, KEY_HOME, KEY_END, KEY_PAGE_UP, KEY_PAGE_DOWN, KEY_INSERT, KEY_DELETE, KEY_F1};
+pub use machine::device::audio::{Audio, AUDIO_REG_CTRL, AUDIO_REG_RATE, AUDIO_REG_CHANNELS, AUDIO_REG_BASE, AUDIO_REG_SIZE, AUDIO_REG_READ, AUDIO_REG_WRITE, AUDIO_REG_UNDERRUNS, AUDIO_REG_IRQ, AUDIO_REG_IRQ_THREAD, AUDIO_REG_THRESHOLD, AUDIO_REG_TONE, AUDIO_REGS_SIZE,
+    AUDIO_CTRL_ENABLE, AUDIO_IRQ_ENABLE, AUDIO_TONES, AUDIO_TONE_STRIDE, AUDIO_TONE_FREQ, AUDIO_TONE_VOLUME, AUDIO_TONE_WAVE, AUDIO_WAVE_SQUARE, AUDIO_WAVE_TRIANGLE, AUDIO_WAVE_SAWTOOTH, AUDIO_WAVE_NOISE};
+pub use machine::thread::{REG_I, REG_B, REG_S, REG_F, REG_C, REG_H, REG_V, REG_M, REG_N, REG_P, REG_A, REG_R, NUM_REGS, NUM_INTERRUPTS, FLAG_BIT_Z, FLAG_BIT_S, FLAG_BIT_C, FLAG_BIT_E, FLAG_BIT_L, FLAG_BIT_I};
+pub use machine::thread::{PERM_BIT_SPAWN, PERM_BIT_DEVICE, PERM_BIT_MEM_RANGE, PERM_BIT_ATOMIC, PERM_BIT_HOST_FS, PERM_BIT_SUPERVISOR, PR_PERMISSIONS, PR_ACCESS_MIN, PR_ACCESS_MAX, PR_PAGE_TABLE};
+pub use machine::thread::mmu::{PAGE_SIZE, PTE_PRESENT, PTE_READ, PTE_WRITE, PTE_EXECUTE, PTE_USER, PTE_FRAME, VECTOR_PAGE_FAULT, FAULT_READ, FAULT_WRITE, FAULT_EXECUTE, FAULT_PROTECTION};
+pub use machine::thread::syscalls::{SYS_OPEN, SYS_CLOSE, SYS_READ, SYS_WRITE, SYS_SEEK, SYS_STAT, SYS_READDIR};
+pub use machine::host_fs::{HostFs, SysError, OPEN_READ, OPEN_WRITE, OPEN_CREATE, OPEN_TRUNCATE, OPEN_APPEND, STAT_FILE, STAT_DIR};
+pub use machine::dma::{DMA_DESC_MODE, DMA_DESC_SRC, DMA_DESC_DST, DMA_DESC_LEN, DMA_DESC_STATUS, DMA_DESC_IRQ, DMA_DESC_SIZE, DMA_MEM_TO_MEM, DMA_MEM_TO_DEVICE, DMA_DEVICE_TO_MEM,
+    DMA_STATUS_BUSY, DMA_STATUS_DONE, DMA_STATUS_ERROR, DMA_IRQ_ENABLE, DMA_QUEUE_LEN};
 pub use assembler::assemble;
\ No newline at end of file
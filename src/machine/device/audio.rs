@@ -0,0 +1,473 @@
+use std::{fs::File, io::{BufWriter, Seek, SeekFrom, Write}, path::Path, sync::{Arc, Mutex}};
+
+use crate::machine::VmError;
+
+use super::{Device, Bus, load_registers, store8_registers};
+
+/// r/w: bit 0 plays the ring buffer and the tones, paced by virtual time. nothing is rendered while it is clear
+pub const AUDIO_REG_CTRL: u32 = 0x00;
+/// r: frames per second
+pub const AUDIO_REG_RATE: u32 = 0x04;
+/// r: samples per frame, 1 or 2
+pub const AUDIO_REG_CHANNELS: u32 = 0x08;
+/// r/w: ram address of the ring buffer of 16 bit little endian samples, frames interleaved. a write empties the buffer
+pub const AUDIO_REG_BASE: u32 = 0x0C;
+/// r/w: size of the ring buffer in bytes, rounded down to whole frames. a write empties the buffer,
+/// sizes which round down to 0 or are larger than the ram are ignored
+pub const AUDIO_REG_SIZE: u32 = 0x10;
+/// r: byte offset of the next sample the device plays
+pub const AUDIO_REG_READ: u32 = 0x14;
+/// r/w: byte offset behind the last sample written by the guest. the buffer is empty if it equals `AUDIO_REG_READ`
+pub const AUDIO_REG_WRITE: u32 = 0x18;
+/// r: number of frames played as silence because the buffer was empty. any write resets it
+pub const AUDIO_REG_UNDERRUNS: u32 = 0x1C;
+/// r/w: interrupt raised while the buffer holds at most `AUDIO_REG_THRESHOLD` bytes: bit 31 enables it, the low bits are the vector
+pub const AUDIO_REG_IRQ: u32 = 0x20;
+/// r/w: thread the interrupt is raised on
+pub const AUDIO_REG_IRQ_THREAD: u32 = 0x24;
+/// r/w: fill level in bytes at which the interrupt is raised
+pub const AUDIO_REG_THRESHOLD: u32 = 0x28;
+/// first tone channel, each has `AUDIO_TONE_*` registers and takes `AUDIO_TONE_STRIDE` bytes
+pub const AUDIO_REG_TONE: u32 = 0x30;
+/// size of the register block to map
+pub const AUDIO_REGS_SIZE: u32 = AUDIO_REG_TONE + AUDIO_TONES * AUDIO_TONE_STRIDE;
+
+/// plays the ring buffer and the tones
+pub const AUDIO_CTRL_ENABLE: u32 = 1 << 0;
+/// enables `AUDIO_REG_IRQ`
+pub const AUDIO_IRQ_ENABLE: u32 = 1 << 31;
+
+/// number of tone channels
+pub const AUDIO_TONES: u32 = 4;
+/// bytes between the registers of two tone channels
+pub const AUDIO_TONE_STRIDE: u32 = 0x10;
+/// r/w: frequency of a tone channel in hertz
+pub const AUDIO_TONE_FREQ: u32 = 0x00;
+/// r/w: volume of a tone channel, 0 (off) to 255. all channels at full volume add up to full scale
+pub const AUDIO_TONE_VOLUME: u32 = 0x04;
+/// r/w: waveform of a tone channel, see `AUDIO_WAVE_*`
+pub const AUDIO_TONE_WAVE: u32 = 0x08;
+
+pub const AUDIO_WAVE_SQUARE: u32 = 0;
+pub const AUDIO_WAVE_TRIANGLE: u32 = 1;
+pub const AUDIO_WAVE_SAWTOOTH: u32 = 2;
+pub const AUDIO_WAVE_NOISE: u32 = 3;
+
+/// frames rendered per wakeup
+const CHUNK: u64 = 256;
+
+/// PCM output. The guest fills a ring buffer in ram and the device plays it at the sample rate, mixed with up to 4 tone generators.
+/// Time is virtual like with `Clock`, so a run renders the same samples no matter how fast the host is.
+/// The output is recorded in memory or written to a wav file.
+///
+/// Map the registers with `Machine::with_mmio_device`, `dev_flush` finishes the wav file.
+/// The recording is shared with every clone, so a clone kept by the host can read `samples` or `save_wav` once the guest played.
+#[derive(Clone)]
+pub struct Audio {
+    inner: Arc<Mutex<AudioState>>,
+}
+
+#[derive(Default, Clone, Copy)]
+struct Tone {
+    freq: u32,
+    volume: u32,
+    wave: u32,
+    phase: u32,
+    noise: u16,
+}
+
+struct AudioState {
+    bus: Option<Bus>,
+    rate: u32,
+    channels: u32,
+    tick_ns: u32,
+    ctrl: u32,
+    base: u32,
+    size: u32,
+    read: u32,
+    write: u32,
+    underruns: u32,
+    irq: u32,
+    irq_thread: u32,
+    threshold: u32,
+    tones: [Tone; AUDIO_TONES as usize],
+    /// tick playback was enabled at and frames rendered since then
+    start_tick: u64,
+    rendered: u64,
+    frames: u64,
+    /// the recording, if it is not written to a wav file
+    samples: Vec<i16>,
+    wav: Option<(BufWriter<File>, u32)>,
+    wav_error: Option<VmError>,
+}
+
+impl Audio {
+    /// output with rate frames per second of 1 or 2 channels, recorded in memory. a virtual tick is 10ns
+    pub fn new(rate: u32, channels: u32) -> Self {
+        Self { inner: Arc::new(Mutex::new(AudioState {
+            bus: None,
+            rate: rate.max(1),
+            channels: channels.clamp(1, 2),
+            tick_ns: 10,
+            ctrl: 0,
+            base: 0,
+            size: 0,
+            read: 0,
+            write: 0,
+            underruns: 0,
+            irq: 0,
+            irq_thread: 0,
+            threshold: 0,
+            tones: [Tone::default(); AUDIO_TONES as usize],
+            start_tick: 0,
+            rendered: 0,
+            frames: 0,
+            samples: vec![],
+            wav: None,
+            wav_error: None,
+        })) }
+    }
+
+    /// nanoseconds per virtual tick, at least 1. should match the `Clock` of the machine
+    pub fn with_tick_ns(self, tick_ns: u32) -> Self {
+        self.state().tick_ns = tick_ns.max(1);
+        self
+    }
+
+    /// streams the output to a wav file instead of recording it in memory
+    pub fn with_wav<P: AsRef<Path>>(self, path: P) -> Result<Self, VmError> {
+        {
+            let mut state = self.state();
+            let mut file = BufWriter::new(File::create(path)?);
+            file.write_all(&wav_header(state.rate, state.channels, 0))?;
+            state.wav = Some((file, 0));
+        }
+        Ok(self)
+    }
+
+    /// number of rendered frames
+    pub fn frames(&self) -> u64 {
+        self.state().frames
+    }
+
+    /// the samples recorded in memory, frames interleaved. empty when writing a wav file
+    pub fn samples(&self) -> Vec<i16> {
+        self.state().samples.clone()
+    }
+
+    /// saves the samples recorded in memory as a wav file
+    pub fn save_wav<P: AsRef<Path>>(&self, path: P) -> Result<(), VmError> {
+        let state = self.state();
+        let mut file = BufWriter::new(File::create(path)?);
+        file.write_all(&wav_header(state.rate, state.channels, (state.samples.len() * 2).min(u32::MAX as usize) as u32))?;
+        for sample in &state.samples {
+            file.write_all(&sample.to_le_bytes())?;
+        }
+        file.flush()?;
+        Ok(())
+    }
+
+    /// writes the final length to the wav file. also happens on `dev_flush` and once the machine and all clones are dropped
+    pub fn finish(&self) -> Result<(), VmError> {
+        let mut state = self.state();
+        state.finish_wav();
+        state.wav_error.take().map_or(Ok(()), Err)
+    }
+
+    /// the first error of writing the wav file since the last call
+    pub fn take_wav_error(&self) -> Option<VmError> {
+        self.state().wav_error.take()
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, AudioState> {
+        self.inner.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// canonical 44 byte header of 16 bit pcm
+fn wav_header(rate: u32, channels: u32, data_len: u32) -> [u8; 44] {
+    let mut header = [0u8; 44];
+    let block_align = channels * 2;
+    header[0..4].copy_from_slice(b"RIFF");
+    header[4..8].copy_from_slice(&data_len.saturating_add(36).to_le_bytes());
+    header[8..16].copy_from_slice(b"WAVEfmt ");
+    header[16..20].copy_from_slice(&16u32.to_le_bytes());
+    // pcm
+    header[20..22].copy_from_slice(&1u16.to_le_bytes());
+    header[22..24].copy_from_slice(&(channels as u16).to_le_bytes());
+    header[24..28].copy_from_slice(&rate.to_le_bytes());
+    header[28..32].copy_from_slice(&rate.saturating_mul(block_align).to_le_bytes());
+    header[32..34].copy_from_slice(&(block_align as u16).to_le_bytes());
+    header[34..36].copy_from_slice(&16u16.to_le_bytes());
+    header[36..40].copy_from_slice(b"data");
+    header[40..44].copy_from_slice(&data_len.to_le_bytes());
+    header
+}
+
+impl Tone {
+    fn next(&mut self, rate: u32) -> i32 {
+        if self.volume == 0 || self.freq == 0 { return 0; }
+        let step = ((self.freq as u64) << 32) / rate as u64;
+        let phase = self.phase;
+        let wrapped;
+        (self.phase, wrapped) = phase.overflowing_add(step as u32);
+        if wrapped || self.noise == 0 {
+            // 16 bit galois lfsr, stepped once per period
+            self.noise = if self.noise == 0 { 0xACE1 } else { (self.noise >> 1) ^ (0u16.wrapping_sub(self.noise & 1) & 0xB400) };
+        }
+        // -0x8000..0x8000
+        let value = match self.wave {
+            AUDIO_WAVE_SQUARE => if phase < 1 << 31 { 0x7FFF } else { -0x8000 },
+            AUDIO_WAVE_TRIANGLE => {
+                let ramp = (phase >> 15) as i32;
+                if ramp < 0x10000 { ramp - 0x8000 } else { 0x17FFF - ramp }
+            },
+            AUDIO_WAVE_SAWTOOTH => (phase >> 16) as i32 - 0x8000,
+            AUDIO_WAVE_NOISE => if self.noise & 1 != 0 { 0x7FFF } else { -0x8000 },
+            _ => 0
+        };
+        value * self.volume.min(255) as i32 / 255 / AUDIO_TONES as i32
+    }
+}
+
+impl AudioState {
+    fn now(&self) -> u64 {
+        self.bus.as_ref().and_then(|bus| bus.ticks().ok()).unwrap_or(self.start_tick)
+    }
+
+    fn frame_bytes(&self) -> u32 {
+        self.channels * 2
+    }
+
+    /// bytes in the ring buffer the guest wrote and the device did not play yet
+    fn fill(&self) -> u32 {
+        if self.size == 0 { return 0; }
+        ((self.write as u64 + self.size as u64 - self.read as u64) % self.size as u64) as u32
+    }
+
+    /// ticks after start_tick at which frames are due
+    fn ticks_for(&self, frames: u64) -> u64 {
+        let ticks = frames as u128 * 1_000_000_000 / (self.rate as u128 * self.tick_ns as u128);
+        ticks.min(u64::MAX as u128) as u64
+    }
+
+    /// renders all frames which are due by now
+    fn render_until(&mut self, now: u64) {
+        let due = (now.saturating_sub(self.start_tick) as u128 * self.tick_ns as u128 * self.rate as u128 / 1_000_000_000).min(u64::MAX as u128) as u64;
+        if due > self.rendered {
+            // a long pause without wakeups is rendered in chunks
+            while self.rendered < due {
+                let n = (due - self.rendered).min(CHUNK * 16);
+                self.render(n as u32);
+                self.rendered += n;
+            }
+        }
+    }
+
+    fn render(&mut self, frames: u32) {
+        let channels = self.channels as usize;
+        let mut out = vec![0i32; frames as usize * channels];
+        // ring buffer
+        let available = self.fill() / self.frame_bytes();
+        let taken = frames.min(available);
+        let mut bytes = vec![0u8; (taken * self.frame_bytes()) as usize];
+        let first = bytes.len().min((self.size - self.read.min(self.size)) as usize);
+        let ok = match &self.bus {
+            Some(bus) => bus.read_memory(self.base.wrapping_add(self.read), &mut bytes[..first]).is_ok()
+                && bus.read_memory(self.base, &mut bytes[first..]).is_ok(),
+            None => false,
+        };
+        let taken = if ok { taken } else { 0 };
+        for (sample, bytes) in out.iter_mut().zip(bytes.chunks(2).take(taken as usize * channels)) {
+            *sample = i16::from_le_bytes([bytes[0], bytes[1]]) as i32;
+        }
+        if taken > 0 {
+            self.read = ((self.read as u64 + (taken * self.frame_bytes()) as u64) % self.size as u64) as u32;
+        }
+        self.underruns = self.underruns.saturating_add(frames - taken);
+        // tones
+        let rate = self.rate;
+        for frame in out.chunks_mut(channels) {
+            let tone = self.tones.iter_mut().map(|tone| tone.next(rate)).sum::<i32>();
+            for sample in frame {
+                *sample += tone;
+            }
+        }
+        self.output(&out);
+        self.frames += frames as u64;
+        if self.irq & AUDIO_IRQ_ENABLE != 0 && self.fill() <= self.threshold && let Some(bus) = &self.bus {
+            let _ = bus.raise_interrupt(self.irq_thread, self.irq & !AUDIO_IRQ_ENABLE);
+        }
+    }
+
+    fn output(&mut self, samples: &[i32]) {
+        let samples = samples.iter().map(|sample| (*sample).clamp(i16::MIN as i32, i16::MAX as i32) as i16);
+        let Some((file, len)) = &mut self.wav else {
+            self.samples.extend(samples);
+            return;
+        };
+        let mut result = Ok(());
+        for sample in samples {
+            result = result.and_then(|_| file.write_all(&sample.to_le_bytes()));
+            *len = len.saturating_add(2);
+        }
+        if let Err(e) = result {
+            self.wav_error.get_or_insert(e.into());
+        }
+    }
+
+    fn finish_wav(&mut self) {
+        let Some((file, len)) = &mut self.wav else { return };
+        let header = wav_header(self.rate, self.channels, *len);
+        let result: std::io::Result<()> = try {
+            file.flush()?;
+            let file = file.get_mut();
+            file.seek(SeekFrom::Start(0))?;
+            file.write_all(&header)?;
+            file.seek(SeekFrom::End(0))?;
+        };
+        if let Err(e) = result {
+            self.wav_error.get_or_insert(e.into());
+        }
+    }
+
+    fn wake(&self) {
+        if self.ctrl & AUDIO_CTRL_ENABLE == 0 { return; }
+        let next = self.start_tick.saturating_add(self.ticks_for(self.rendered + CHUNK)).max(self.now() + 1);
+        if let Some(bus) = &self.bus {
+            let _ = bus.wake_at(next);
+        }
+    }
+
+    fn load(&self, offset: u32) -> u32 {
+        if (AUDIO_REG_TONE..AUDIO_REGS_SIZE).contains(&offset) {
+            let tone = &self.tones[((offset - AUDIO_REG_TONE) / AUDIO_TONE_STRIDE) as usize];
+            return match (offset - AUDIO_REG_TONE) % AUDIO_TONE_STRIDE {
+                AUDIO_TONE_FREQ => tone.freq,
+                AUDIO_TONE_VOLUME => tone.volume,
+                AUDIO_TONE_WAVE => tone.wave,
+                _ => 0
+            };
+        }
+        match offset {
+            AUDIO_REG_CTRL => self.ctrl,
+            AUDIO_REG_RATE => self.rate,
+            AUDIO_REG_CHANNELS => self.channels,
+            AUDIO_REG_BASE => self.base,
+            AUDIO_REG_SIZE => self.size,
+            AUDIO_REG_READ => self.read,
+            AUDIO_REG_WRITE => self.write,
+            AUDIO_REG_UNDERRUNS => self.underruns,
+            AUDIO_REG_IRQ => self.irq,
+            AUDIO_REG_IRQ_THREAD => self.irq_thread,
+            AUDIO_REG_THRESHOLD => self.threshold,
+            _ => 0
+        }
+    }
+
+    fn store(&mut self, offset: u32, data: u32) {
+        if (AUDIO_REG_TONE..AUDIO_REGS_SIZE).contains(&offset) {
+            // catch up first, so the change starts at the current time
+            if self.ctrl & AUDIO_CTRL_ENABLE != 0 {
+                self.render_until(self.now());
+            }
+            let tone = &mut self.tones[((offset - AUDIO_REG_TONE) / AUDIO_TONE_STRIDE) as usize];
+            match (offset - AUDIO_REG_TONE) % AUDIO_TONE_STRIDE {
+                AUDIO_TONE_FREQ => tone.freq = data,
+                AUDIO_TONE_VOLUME => tone.volume = data.min(255),
+                AUDIO_TONE_WAVE => tone.wave = data,
+                _ => {}
+            }
+            return;
+        }
+        match offset {
+            AUDIO_REG_CTRL => {
+                let enabled = self.ctrl & AUDIO_CTRL_ENABLE != 0;
+                if enabled {
+                    self.render_until(self.now());
+                }
+                self.ctrl = data & AUDIO_CTRL_ENABLE;
+                if !enabled && self.ctrl & AUDIO_CTRL_ENABLE != 0 {
+                    self.start_tick = self.now();
+                    self.rendered = 0;
+                    self.wake();
+                }
+            },
+            AUDIO_REG_BASE => { self.base = data; self.read = 0; self.write = 0; },
+            AUDIO_REG_SIZE => {
+                // whole frames only
+                let size = data - data % self.frame_bytes();
+                let ram = self.bus.as_ref().and_then(|bus| bus.memory_size().ok()).unwrap_or(0);
+                if size == 0 || size > ram { return; }
+                self.size = size;
+                self.read = 0;
+                self.write = 0;
+            },
+            AUDIO_REG_WRITE => self.write = if self.size == 0 { 0 } else { data % self.size },
+            AUDIO_REG_UNDERRUNS => self.underruns = 0,
+            AUDIO_REG_IRQ => self.irq = data,
+            AUDIO_REG_IRQ_THREAD => self.irq_thread = data,
+            AUDIO_REG_THRESHOLD => self.threshold = data,
+            _ => {}
+        }
+    }
+}
+
+impl Drop for AudioState {
+    fn drop(&mut self) {
+        self.finish_wav();
+    }
+}
+
+impl Device for Audio {
+    fn read8(&mut self) -> u8 { 0 }
+
+    fn write8(&mut self, _data: u8) {}
+
+    /// errors are reported by `Audio::take_wav_error`
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.state().finish_wav();
+        Ok(())
+    }
+
+    fn load8(&mut self, offset: u32) -> u8 {
+        (self.state().load(offset & !3) >> ((offset & 3) * 8)) as u8
+    }
+
+    fn load(&mut self, offset: u32) -> u32 {
+        load_registers(self, offset, |device| device.state().load(offset))
+    }
+
+    fn store8(&mut self, offset: u32, data: u8) {
+        store8_registers(self, offset, data);
+    }
+
+    fn store(&mut self, offset: u32, data: u32) {
+        self.state().store(offset, data);
+    }
+
+    fn attach(&mut self, bus: Bus) {
+        self.state().bus = Some(bus);
+    }
+
+    fn reset(&mut self) {
+        let mut state = self.state();
+        state.ctrl = 0;
+        state.base = 0;
+        state.size = 0;
+        state.read = 0;
+        state.write = 0;
+        state.underruns = 0;
+        state.irq = 0;
+        state.irq_thread = 0;
+        state.threshold = 0;
+        state.tones = [Tone::default(); AUDIO_TONES as usize];
+    }
+
+    fn tick(&mut self, now: u64) {
+        let mut state = self.state();
+        if state.ctrl & AUDIO_CTRL_ENABLE == 0 { return; }
+        state.render_until(now);
+        state.wake();
+    }
+}
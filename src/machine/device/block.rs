@@ -0,0 +1,182 @@
+use std::{fs::{File, OpenOptions}, io::{Read, Seek, SeekFrom, Write}, path::Path};
+
+use crate::machine::VmError;
+
+use super::{Device, Bus, load_registers, store8_registers};
+
+/// bytes per sector
+pub const SECTOR_SIZE: u32 = 512;
+
+/// w: execute a command, see `BLOCK_CMD_*`
+pub const BLOCK_REG_CMD: u32 = 0x00;
+/// r/w: first sector of the transfer
+pub const BLOCK_REG_LBA: u32 = 0x04;
+/// r/w: number of sectors to transfer
+pub const BLOCK_REG_COUNT: u32 = 0x08;
+/// r/w: ram address to transfer from/to
+pub const BLOCK_REG_DMA: u32 = 0x0C;
+/// r: result of the last command, see `BLOCK_STATUS_*`. any write resets it to idle
+pub const BLOCK_REG_STATUS: u32 = 0x10;
+/// r: number of sectors of the disk
+pub const BLOCK_REG_SECTORS: u32 = 0x14;
+/// r/w: interrupt raised on completion: bit 31 enables it, the low bits are the vector
+pub const BLOCK_REG_IRQ: u32 = 0x18;
+/// r/w: thread the interrupt is raised on
+pub const BLOCK_REG_IRQ_THREAD: u32 = 0x1C;
+/// size of the register block to map
+pub const BLOCK_REGS_SIZE: u32 = 0x20;
+
+/// copy sectors from disk to ram
+pub const BLOCK_CMD_READ: u32 = 1;
+/// copy sectors from ram to disk
+pub const BLOCK_CMD_WRITE: u32 = 2;
+/// write all changes to the host file
+pub const BLOCK_CMD_FLUSH: u32 = 3;
+
+pub const BLOCK_STATUS_IDLE: u32 = 0;
+pub const BLOCK_STATUS_DONE: u32 = 1;
+/// invalid command, sectors out of range, ram out of bounds or a host io error
+pub const BLOCK_STATUS_ERROR: u32 = 2;
+
+/// enables `BLOCK_REG_IRQ`
+pub const BLOCK_IRQ_ENABLE: u32 = 1 << 31;
+
+/// Disk backed by a host image file, transferring whole sectors between the disk and ram.
+/// Commands complete before the store to `BLOCK_REG_CMD` returns, after that the status is set and the interrupt raised.
+///
+/// Map the registers with `Machine::with_mmio_device`.
+pub struct BlockDevice {
+    file: File,
+    sectors: u32,
+    bus: Option<Bus>,
+    lba: u32,
+    count: u32,
+    dma: u32,
+    status: u32,
+    irq: u32,
+    irq_thread: u32,
+}
+
+impl BlockDevice {
+    /// opens an existing image. a partial last sector reads as zeros
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, VmError> {
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+        let len = file.metadata()?.len();
+        Ok(Self::new(file, len.div_ceil(SECTOR_SIZE as u64).min(u32::MAX as u64) as u32))
+    }
+
+    /// creates a zeroed image with the given number of sectors, replacing an existing file
+    pub fn create<P: AsRef<Path>>(path: P, sectors: u32) -> Result<Self, VmError> {
+        let file = OpenOptions::new().read(true).write(true).create(true).truncate(true).open(path)?;
+        file.set_len(sectors as u64 * SECTOR_SIZE as u64)?;
+        Ok(Self::new(file, sectors))
+    }
+
+    fn new(file: File, sectors: u32) -> Self {
+        Self { file, sectors, bus: None, lba: 0, count: 0, dma: 0, status: BLOCK_STATUS_IDLE, irq: 0, irq_thread: 0 }
+    }
+
+    /// number of sectors of the disk
+    pub fn sectors(&self) -> u32 {
+        self.sectors
+    }
+
+    fn execute(&mut self, cmd: u32) {
+        let ok = match cmd {
+            BLOCK_CMD_READ => self.transfer(false).is_ok(),
+            BLOCK_CMD_WRITE => self.transfer(true).is_ok(),
+            BLOCK_CMD_FLUSH => self.file.sync_data().is_ok(),
+            _ => false
+        };
+        self.status = if ok { BLOCK_STATUS_DONE } else { BLOCK_STATUS_ERROR };
+        if self.irq & BLOCK_IRQ_ENABLE == 0 { return; }
+        if let Some(bus) = &self.bus {
+            let _ = bus.raise_interrupt(self.irq_thread, self.irq & !BLOCK_IRQ_ENABLE);
+        }
+    }
+
+    fn transfer(&mut self, write: bool) -> Result<(), VmError> {
+        let bus = self.bus.as_ref().ok_or(VmError::Detached)?;
+        if self.lba.checked_add(self.count).is_none_or(|end| end > self.sectors) {
+            return Err(VmError::OutOfBounds { addr: self.lba, len: self.count as usize });
+        }
+        let mut buf = vec![0u8; self.count as usize * SECTOR_SIZE as usize];
+        self.file.seek(SeekFrom::Start(self.lba as u64 * SECTOR_SIZE as u64))?;
+        if write {
+            bus.read_memory(self.dma, &mut buf)?;
+            self.file.write_all(&buf)?;
+        } else {
+            // the file may end inside the last sector
+            let mut read = 0;
+            while read < buf.len() {
+                match self.file.read(&mut buf[read..])? {
+                    0 => break,
+                    n => read += n,
+                }
+            }
+            bus.write_memory(self.dma, &buf)?;
+        }
+        Ok(())
+    }
+
+    fn load_reg(&self, offset: u32) -> u32 {
+        match offset {
+            BLOCK_REG_LBA => self.lba,
+            BLOCK_REG_COUNT => self.count,
+            BLOCK_REG_DMA => self.dma,
+            BLOCK_REG_STATUS => self.status,
+            BLOCK_REG_SECTORS => self.sectors,
+            BLOCK_REG_IRQ => self.irq,
+            BLOCK_REG_IRQ_THREAD => self.irq_thread,
+            _ => 0
+        }
+    }
+}
+
+impl Device for BlockDevice {
+    fn read8(&mut self) -> u8 { 0 }
+
+    fn write8(&mut self, _data: u8) {}
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.file.sync_data()
+    }
+
+    fn load8(&mut self, offset: u32) -> u8 {
+        (self.load_reg(offset & !3) >> ((offset & 3) * 8)) as u8
+    }
+
+    fn load(&mut self, offset: u32) -> u32 {
+        load_registers(self, offset, |device| device.load_reg(offset))
+    }
+
+    fn store8(&mut self, offset: u32, data: u8) {
+        store8_registers(self, offset, data);
+    }
+
+    fn store(&mut self, offset: u32, data: u32) {
+        match offset {
+            BLOCK_REG_CMD => self.execute(data),
+            BLOCK_REG_LBA => self.lba = data,
+            BLOCK_REG_COUNT => self.count = data,
+            BLOCK_REG_DMA => self.dma = data,
+            BLOCK_REG_STATUS => self.status = BLOCK_STATUS_IDLE,
+            BLOCK_REG_IRQ => self.irq = data,
+            BLOCK_REG_IRQ_THREAD => self.irq_thread = data,
+            _ => {}
+        }
+    }
+
+    fn attach(&mut self, bus: Bus) {
+        self.bus = Some(bus);
+    }
+
+    fn reset(&mut self) {
+        self.lba = 0;
+        self.count = 0;
+        self.dma = 0;
+        self.status = BLOCK_STATUS_IDLE;
+        self.irq = 0;
+        self.irq_thread = 0;
+    }
+}
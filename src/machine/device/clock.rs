@@ -0,0 +1,171 @@
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use super::{Device, Bus, load_registers};
+
+/// r: low word of the monotonic time in nanoseconds since the machine started. reading it latches `CLOCK_REG_MONO_HI`
+pub const CLOCK_REG_MONO_LO: u32 = 0x00;
+/// r: high word of the monotonic time, as latched by reading `CLOCK_REG_MONO_LO`
+pub const CLOCK_REG_MONO_HI: u32 = 0x04;
+/// r: low word of the wall clock time in seconds since the unix epoch. reading it latches `CLOCK_REG_WALL_HI` and `CLOCK_REG_WALL_NANOS`
+pub const CLOCK_REG_WALL_LO: u32 = 0x08;
+/// r: high word of the wall clock seconds, as latched by reading `CLOCK_REG_WALL_LO`
+pub const CLOCK_REG_WALL_HI: u32 = 0x0C;
+/// r: nanoseconds of the wall clock second, as latched by reading `CLOCK_REG_WALL_LO`
+pub const CLOCK_REG_WALL_NANOS: u32 = 0x10;
+/// r: nanoseconds per virtual tick, 0 if the clock follows host time
+pub const CLOCK_REG_TICK_NS: u32 = 0x14;
+/// size of the register block to map
+pub const CLOCK_REGS_SIZE: u32 = 0x18;
+
+/// Where a `Clock` takes its time from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ClockSource {
+    /// virtual time with the deterministic scheduler, host time otherwise
+    #[default]
+    Auto,
+    /// the host clocks
+    Host,
+    /// derived from `Machine::ticks`, which makes the time reproducible for the same program
+    Virtual,
+}
+
+/// Wall clock and monotonic time source. With virtual time every tick is a fixed number of nanoseconds
+/// and the wall clock starts at a fixed epoch, so runs with the deterministic scheduler stay reproducible.
+///
+/// Map the registers with `Machine::with_mmio_device`.
+pub struct Clock {
+    bus: Option<Bus>,
+    source: ClockSource,
+    tick_ns: u32,
+    epoch: u64,
+    /// host time and virtual tick at the last reset
+    start: Instant,
+    start_tick: u64,
+    /// high words latched by reading the low words
+    mono_hi: u32,
+    wall_hi: u32,
+    wall_nanos: u32,
+}
+
+impl Default for Clock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock {
+    /// clock with `ClockSource::Auto`, 10ns per virtual tick and the virtual wall clock starting at the unix epoch
+    pub fn new() -> Self {
+        Self {
+            bus: None,
+            source: ClockSource::Auto,
+            tick_ns: 10,
+            epoch: 0,
+            start: Instant::now(),
+            start_tick: 0,
+            mono_hi: 0,
+            wall_hi: 0,
+            wall_nanos: 0,
+        }
+    }
+
+    pub fn with_source(mut self, source: ClockSource) -> Self {
+        self.source = source;
+        self
+    }
+
+    /// nanoseconds per virtual tick, at least 1
+    pub fn with_tick_ns(mut self, tick_ns: u32) -> Self {
+        self.tick_ns = tick_ns.max(1);
+        self
+    }
+
+    /// seconds since the unix epoch the virtual wall clock starts at
+    pub fn with_epoch(mut self, epoch: u64) -> Self {
+        self.epoch = epoch;
+        self
+    }
+
+    fn ticks(&self) -> u64 {
+        self.bus.as_ref().and_then(|bus| bus.ticks().ok()).unwrap_or(self.start_tick)
+    }
+
+    fn is_virtual(&self) -> bool {
+        match self.source {
+            ClockSource::Auto => self.bus.as_ref().is_some_and(|bus| bus.deterministic().unwrap_or(false)),
+            ClockSource::Host => false,
+            ClockSource::Virtual => true,
+        }
+    }
+
+    /// nanoseconds since the last reset
+    fn monotonic(&self) -> u64 {
+        if self.is_virtual() {
+            self.ticks().saturating_sub(self.start_tick).saturating_mul(self.tick_ns as u64)
+        } else {
+            self.start.elapsed().as_nanos().min(u64::MAX as u128) as u64
+        }
+    }
+
+    /// seconds and nanoseconds since the unix epoch
+    fn wall(&self) -> (u64, u32) {
+        if self.is_virtual() {
+            let mono = self.monotonic();
+            (self.epoch.saturating_add(mono / 1_000_000_000), (mono % 1_000_000_000) as u32)
+        } else {
+            // a host clock before 1970 reads as the epoch
+            let since_epoch = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+            (since_epoch.as_secs(), since_epoch.subsec_nanos())
+        }
+    }
+
+    fn load_reg(&mut self, offset: u32) -> u32 {
+        match offset {
+            CLOCK_REG_MONO_LO => {
+                let mono = self.monotonic();
+                self.mono_hi = (mono >> 32) as u32;
+                mono as u32
+            },
+            CLOCK_REG_MONO_HI => self.mono_hi,
+            CLOCK_REG_WALL_LO => {
+                let (secs, nanos) = self.wall();
+                self.wall_hi = (secs >> 32) as u32;
+                self.wall_nanos = nanos;
+                secs as u32
+            },
+            CLOCK_REG_WALL_HI => self.wall_hi,
+            CLOCK_REG_WALL_NANOS => self.wall_nanos,
+            CLOCK_REG_TICK_NS if self.is_virtual() => self.tick_ns,
+            _ => 0
+        }
+    }
+}
+
+impl Device for Clock {
+    fn read8(&mut self) -> u8 { 0 }
+
+    fn write8(&mut self, _data: u8) {}
+
+    fn flush(&mut self) -> std::io::Result<()> { Ok(()) }
+
+    fn load8(&mut self, offset: u32) -> u8 {
+        (self.load_reg(offset & !3) >> ((offset & 3) * 8)) as u8
+    }
+
+    fn load(&mut self, offset: u32) -> u32 {
+        load_registers(self, offset, |device| device.load_reg(offset))
+    }
+
+    fn attach(&mut self, bus: Bus) {
+        self.start_tick = bus.ticks().unwrap_or(0);
+        self.bus = Some(bus);
+    }
+
+    fn reset(&mut self) {
+        self.start = Instant::now();
+        self.start_tick = self.ticks();
+        self.mono_hi = 0;
+        self.wall_hi = 0;
+        self.wall_nanos = 0;
+    }
+}
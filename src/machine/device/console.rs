@@ -0,0 +1,161 @@
+use std::{collections::VecDeque, io::{IsTerminal, Read, Write}, sync::{Arc, Mutex}};
+
+use getch::Getch;
+
+use super::Device;
+
+/// Byte stream device behind `write_stdout`, `read_stdin` and `flush_stdout`.
+/// By default it reads single key presses from stdin and writes to stdout, but any reader and writer can be plugged in,
+/// like files, pipes or a `SharedBuffer`. Reading returns 0 once the input ended.
+/// The input is read without holding the device, so a thread waiting for input does not stall writes of other threads.
+pub struct Console {
+    input: Arc<Mutex<ConsoleInput>>,
+    output: Box<dyn Write + Send>,
+}
+
+enum ConsoleInput {
+    /// the terminal, if stdin is one, is switched to raw mode for each read and restored right after
+    Stdin,
+    Reader(Box<dyn Read + Send>),
+}
+
+impl Default for Console {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Console {
+    /// reads from stdin and writes to stdout
+    pub fn new() -> Self {
+        Self {
+            input: Arc::new(Mutex::new(ConsoleInput::Stdin)),
+            output: Box::new(std::io::stdout())
+        }
+    }
+
+    pub fn from_streams(input: Box<dyn Read + Send>, output: Box<dyn Write + Send>) -> Self {
+        Self {
+            input: Arc::new(Mutex::new(ConsoleInput::Reader(input))),
+            output
+        }
+    }
+
+    pub fn with_input(mut self, input: Box<dyn Read + Send>) -> Self {
+        self.input = Arc::new(Mutex::new(ConsoleInput::Reader(input)));
+        self
+    }
+
+    pub fn with_output(mut self, output: Box<dyn Write + Send>) -> Self {
+        self.output = output;
+        self
+    }
+}
+
+impl Read for ConsoleInput {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            ConsoleInput::Stdin if buf.is_empty() || !std::io::stdin().is_terminal() => std::io::stdin().read(buf),
+            ConsoleInput::Stdin => {
+                buf[0] = Getch::new().getch()?;
+                Ok(1)
+            },
+            ConsoleInput::Reader(input) => input.read(buf)
+        }
+    }
+}
+
+/// reads a single byte, 0 once the input ended or failed
+pub(crate) fn read_byte(input: &mut (impl Read + ?Sized)) -> u8 {
+    let mut buf = [0u8];
+    input.read_exact(&mut buf).map(|_| buf[0]).unwrap_or(0)
+}
+
+impl Device for Console {
+    fn read8(&mut self) -> u8 {
+        read_byte(&mut *self.input.lock().unwrap_or_else(|poisoned| poisoned.into_inner()))
+    }
+
+    fn write8(&mut self, data: u8) {
+        let _ = self.output.write_all(&[data]);
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.output.flush()
+    }
+
+    fn blocking_input(&mut self) -> Option<Arc<Mutex<dyn Read + Send>>> {
+        Some(self.input.clone())
+    }
+}
+
+/// In-memory stream to script console input or capture console output. Reads take bytes from the front,
+/// writes append to the back. Clones share the same buffer, keep one to access it from the host.
+#[derive(Clone, Default)]
+pub struct SharedBuffer {
+    inner: Arc<Mutex<VecDeque<u8>>>,
+}
+
+impl SharedBuffer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// appends bytes for the next reads
+    pub fn push(&self, data: &[u8]) {
+        self.buffer().extend(data);
+    }
+
+    /// removes and returns everything in the buffer
+    pub fn take(&self) -> Vec<u8> {
+        self.buffer().drain(..).collect()
+    }
+
+    /// everything in the buffer, as utf-8 with invalid sequences replaced
+    pub fn contents(&self) -> String {
+        String::from_utf8_lossy(self.buffer().make_contiguous()).into_owned()
+    }
+
+    pub fn len(&self) -> usize {
+        self.buffer().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buffer().is_empty()
+    }
+
+    fn buffer(&self) -> std::sync::MutexGuard<'_, VecDeque<u8>> {
+        self.inner.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl From<&[u8]> for SharedBuffer {
+    fn from(data: &[u8]) -> Self {
+        let buffer = Self::new();
+        buffer.push(data);
+        buffer
+    }
+}
+
+impl From<&str> for SharedBuffer {
+    fn from(data: &str) -> Self {
+        data.as_bytes().into()
+    }
+}
+
+impl Read for SharedBuffer {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.buffer().read(buf)
+    }
+}
+
+impl Write for SharedBuffer {
+    fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+        self.buffer().extend(data);
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,228 @@
+use std::{path::PathBuf, sync::{Arc, Mutex}};
+
+use crate::machine::VmError;
+
+use super::{Device, Bus, load_registers, image::Image};
+
+/// r/w: address of the pixel data in ram
+pub const FB_REG_BASE: u32 = 0x00;
+/// r: width in pixels
+pub const FB_REG_WIDTH: u32 = 0x04;
+/// r: height in pixels
+pub const FB_REG_HEIGHT: u32 = 0x08;
+/// r: pixel format, see `PixelFormat`
+pub const FB_REG_FORMAT: u32 = 0x0C;
+/// w: any write presents the current pixel data as the next frame
+pub const FB_REG_PRESENT: u32 = 0x10;
+/// r: number of presented frames
+pub const FB_REG_FRAME: u32 = 0x14;
+/// size of the register block to map
+pub const FB_REGS_SIZE: u32 = 0x18;
+
+/// Layout of a pixel in ram, pixels are stored row by row without padding
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PixelFormat {
+    /// 4 bytes: r, g, b, unused
+    Rgbx8888 = 0,
+    /// 2 bytes: little endian rrrrrggg gggbbbbb
+    Rgb565 = 1,
+    /// 1 byte gray value
+    Gray8 = 2,
+}
+
+impl PixelFormat {
+    pub fn bytes_per_pixel(self) -> u32 {
+        match self {
+            PixelFormat::Rgbx8888 => 4,
+            PixelFormat::Rgb565 => 2,
+            PixelFormat::Gray8 => 1,
+        }
+    }
+
+    fn to_rgb(self, px: &[u8]) -> [u8;3] {
+        match self {
+            PixelFormat::Rgbx8888 => [px[0], px[1], px[2]],
+            PixelFormat::Rgb565 => {
+                let v = u16::from_le_bytes([px[0], px[1]]);
+                let (r, g, b) = ((v >> 11) & 0x1F, (v >> 5) & 0x3F, v & 0x1F);
+                [(r << 3 | r >> 2) as u8, (g << 2 | g >> 4) as u8, (b << 3 | b >> 2) as u8]
+            },
+            PixelFormat::Gray8 => [px[0], px[0], px[0]],
+        }
+    }
+}
+
+/// Image file format of automatic dumps
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DumpFormat {
+    Ppm,
+    Png,
+}
+
+/// Headless display. The guest draws into ram at `FB_REG_BASE` and writes `FB_REG_PRESENT` to finish a frame,
+/// which is copied out of ram so the host can look at or dump it even after the machine stopped.
+///
+/// Map the registers with `Machine::with_mmio_device`, `dev_write` and `dev_write8` present a frame as well.
+/// A clone kept by the host sees every frame the guest presents through `frame`, `frame_count` and `save_png`.
+#[derive(Clone)]
+pub struct Framebuffer {
+    inner: Arc<Mutex<FramebufferState>>,
+}
+
+struct FramebufferState {
+    bus: Option<Bus>,
+    width: u32,
+    height: u32,
+    format: PixelFormat,
+    base: u32,
+    initial_base: u32,
+    frame: u32,
+    image: Image,
+    dump: Option<(PathBuf, u32, DumpFormat)>,
+    dump_error: Option<VmError>,
+}
+
+impl Framebuffer {
+    pub fn new(width: u32, height: u32, format: PixelFormat) -> Self {
+        Self { inner: Arc::new(Mutex::new(FramebufferState {
+            bus: None,
+            width,
+            height,
+            format,
+            base: 0,
+            initial_base: 0,
+            frame: 0,
+            image: Image::new(width, height),
+            dump: None,
+            dump_error: None,
+        })) }
+    }
+
+    /// address of the pixel data after a reset
+    pub fn with_base(self, addr: u32) -> Self {
+        {
+            let mut state = self.state();
+            state.base = addr;
+            state.initial_base = addr;
+        }
+        self
+    }
+
+    /// saves every nth presented frame to `dir/frame_000000.png` (or `.ppm`), numbered by frame
+    pub fn with_dumps<P: Into<PathBuf>>(self, dir: P, every: u32, format: DumpFormat) -> Self {
+        self.state().dump = Some((dir.into(), every.max(1), format));
+        self
+    }
+
+    /// number of presented frames
+    pub fn frame_count(&self) -> u32 {
+        self.state().frame
+    }
+
+    /// the last presented frame, black before the first one
+    pub fn frame(&self) -> Image {
+        self.state().image.clone()
+    }
+
+    pub fn save_ppm<P: AsRef<std::path::Path>>(&self, path: P) -> Result<(), VmError> {
+        Ok(self.state().image.save_ppm(path)?)
+    }
+
+    pub fn save_png<P: AsRef<std::path::Path>>(&self, path: P) -> Result<(), VmError> {
+        Ok(self.state().image.save_png(path)?)
+    }
+
+    /// the first error of an automatic dump since the last call
+    pub fn take_dump_error(&self) -> Option<VmError> {
+        self.state().dump_error.take()
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, FramebufferState> {
+        self.inner.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl FramebufferState {
+    fn present(&mut self) {
+        let bpp = self.format.bytes_per_pixel();
+        let mut data = vec![0u8; self.width as usize * self.height as usize * bpp as usize];
+        // a frame outside of memory stays black
+        if let Some(bus) = &self.bus {
+            let _ = bus.read_memory(self.base, &mut data);
+        }
+        for (i, px) in data.chunks(bpp as usize).enumerate() {
+            let (x, y) = (i as u32 % self.width, i as u32 / self.width);
+            self.image.set_pixel(x, y, self.format.to_rgb(px));
+        }
+        self.frame = self.frame.wrapping_add(1);
+        let Some((dir, every, format)) = &self.dump else { return };
+        if self.frame.is_multiple_of(*every) {
+            let result = match format {
+                DumpFormat::Ppm => self.image.save_ppm(dir.join(format!("frame_{:06}.ppm", self.frame))),
+                DumpFormat::Png => self.image.save_png(dir.join(format!("frame_{:06}.png", self.frame))),
+            };
+            if let Err(e) = result {
+                self.dump_error.get_or_insert(e.into());
+            }
+        }
+    }
+
+    fn load(&self, offset: u32) -> u32 {
+        match offset {
+            FB_REG_BASE => self.base,
+            FB_REG_WIDTH => self.width,
+            FB_REG_HEIGHT => self.height,
+            FB_REG_FORMAT => self.format as u32,
+            FB_REG_FRAME => self.frame,
+            _ => 0
+        }
+    }
+}
+
+impl Device for Framebuffer {
+    fn read8(&mut self) -> u8 { 0 }
+
+    fn write8(&mut self, _data: u8) {
+        self.state().present();
+    }
+
+    fn write(&mut self, _data: u32) {
+        self.state().present();
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> { Ok(()) }
+
+    fn load8(&mut self, offset: u32) -> u8 {
+        (self.state().load(offset & !3) >> ((offset & 3) * 8)) as u8
+    }
+
+    fn load(&mut self, offset: u32) -> u32 {
+        load_registers(self, offset, |device| device.state().load(offset))
+    }
+
+    fn store8(&mut self, offset: u32, _data: u8) {
+        // only whole words can change the base
+        if offset & !3 == FB_REG_PRESENT {
+            self.state().present();
+        }
+    }
+
+    fn store(&mut self, offset: u32, data: u32) {
+        match offset {
+            FB_REG_BASE => self.state().base = data,
+            FB_REG_PRESENT => self.state().present(),
+            _ => {}
+        }
+    }
+
+    fn attach(&mut self, bus: Bus) {
+        self.state().bus = Some(bus);
+    }
+
+    fn reset(&mut self) {
+        let mut state = self.state();
+        state.base = state.initial_base;
+        state.frame = 0;
+        state.image = Image::new(state.width, state.height);
+    }
+}
@@ -0,0 +1,138 @@
+use std::{io::Write, path::Path};
+
+/// 8 bit rgb image, rows top to bottom
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    /// 3 bytes per pixel: r, g, b
+    pub pixels: Vec<u8>,
+}
+
+impl Image {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self { width, height, pixels: vec![0; width as usize * height as usize * 3] }
+    }
+
+    pub fn pixel(&self, x: u32, y: u32) -> [u8;3] {
+        let i = (y as usize * self.width as usize + x as usize) * 3;
+        [self.pixels[i], self.pixels[i + 1], self.pixels[i + 2]]
+    }
+
+    pub fn set_pixel(&mut self, x: u32, y: u32, rgb: [u8;3]) {
+        let i = (y as usize * self.width as usize + x as usize) * 3;
+        self.pixels[i..i + 3].copy_from_slice(&rgb);
+    }
+
+    /// binary ppm (P6)
+    pub fn write_ppm<W: Write>(&self, mut out: W) -> std::io::Result<()> {
+        write!(out, "P6\n{} {}\n255\n", self.width, self.height)?;
+        out.write_all(&self.pixels)
+    }
+
+    /// uncompressed png
+    pub fn write_png<W: Write>(&self, mut out: W) -> std::io::Result<()> {
+        out.write_all(b"\x89PNG\r\n\x1a\n")?;
+        let mut header = Vec::with_capacity(13);
+        header.extend_from_slice(&self.width.to_be_bytes());
+        header.extend_from_slice(&self.height.to_be_bytes());
+        // bit depth 8, color type rgb, deflate, no filter, no interlace
+        header.extend_from_slice(&[8, 2, 0, 0, 0]);
+        write_chunk(&mut out, b"IHDR", &header)?;
+        let row_len = self.width as usize * 3;
+        let mut raw = Vec::with_capacity((row_len + 1) * self.height as usize);
+        for row in self.pixels.chunks(row_len.max(1)).take(self.height as usize) {
+            // filter type none
+            raw.push(0);
+            raw.extend_from_slice(row);
+        }
+        write_chunk(&mut out, b"IDAT", &zlib_stored(&raw))?;
+        write_chunk(&mut out, b"IEND", &[])
+    }
+
+    pub fn save_ppm<P: AsRef<Path>>(&self, path: P) -> std::io::Result<()> {
+        self.write_ppm(std::io::BufWriter::new(std::fs::File::create(path)?))
+    }
+
+    pub fn save_png<P: AsRef<Path>>(&self, path: P) -> std::io::Result<()> {
+        self.write_png(std::io::BufWriter::new(std::fs::File::create(path)?))
+    }
+}
+
+fn write_chunk<W: Write>(out: &mut W, ty: &[u8;4], data: &[u8]) -> std::io::Result<()> {
+    out.write_all(&(data.len() as u32).to_be_bytes())?;
+    out.write_all(ty)?;
+    out.write_all(data)?;
+    let crc = crc32(crc32(!0, ty), data);
+    out.write_all(&(!crc).to_be_bytes())
+}
+
+/// zlib stream of uncompressed deflate blocks
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len() + data.len() / 0xFFFF * 5 + 11);
+    out.extend_from_slice(&[0x78, 0x01]);
+    let mut blocks = data.chunks(0xFFFF).peekable();
+    if blocks.peek().is_none() {
+        out.extend_from_slice(&[1, 0, 0, 0xFF, 0xFF]);
+    }
+    while let Some(block) = blocks.next() {
+        out.push(blocks.peek().is_none() as u8);
+        out.extend_from_slice(&(block.len() as u16).to_le_bytes());
+        out.extend_from_slice(&(!(block.len() as u16)).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+fn crc32(mut crc: u32, data: &[u8]) -> u32 {
+    for b in data {
+        crc ^= *b as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB88320 } else { crc >> 1 };
+        }
+    }
+    crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for chunk in data.chunks(5552) {
+        for x in chunk {
+            a += *x as u32;
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+    b << 16 | a
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checksums_match_known_vectors() {
+        assert_eq!(!crc32(!0, b""), 0);
+        assert_eq!(!crc32(!0, b"123456789"), 0xCBF43926);
+        assert_eq!(!crc32(crc32(!0, b"IEND"), b""), 0xAE426082);
+        assert_eq!(adler32(b""), 1);
+        assert_eq!(adler32(b"Wikipedia"), 0x11E60398);
+        // long enough to wrap the sums more than once
+        assert_eq!(adler32(&[0xFF; 10000]), 0xB623EB2B);
+    }
+
+    #[test]
+    fn stored_blocks_split_at_their_maximum_length() {
+        let data = (0..0x1_0000u32).map(|i| i as u8).collect::<Vec<_>>();
+        let zlib = zlib_stored(&data);
+        assert_eq!(zlib[..2], [0x78, 0x01]);
+        // a full block which is not the last one, then a final block of one byte
+        assert_eq!(zlib[2..7], [0, 0xFF, 0xFF, 0, 0]);
+        assert_eq!(zlib[7 + 0xFFFF..12 + 0xFFFF], [1, 1, 0, 0xFE, 0xFF]);
+        assert_eq!(zlib[12 + 0xFFFF], data[0xFFFF]);
+        assert_eq!(zlib[13 + 0xFFFF..], 0xBBBA8772u32.to_be_bytes());
+        assert_eq!(zlib_stored(&[]), [0x78, 0x01, 1, 0, 0, 0xFF, 0xFF, 0, 0, 0, 1]);
+    }
+}
@@ -0,0 +1,339 @@
+use std::{collections::VecDeque, sync::{Arc, Mutex}};
+
+use super::{Device, Bus, load_registers, store8_registers};
+
+/// r: takes the next event from the buffer, 0 if it is empty
+pub const KBD_REG_DATA: u32 = 0x00;
+/// r: number of buffered events
+pub const KBD_REG_COUNT: u32 = 0x04;
+/// r/w: interrupt raised for every new event: bit 31 enables it, the low bits are the vector
+pub const KBD_REG_IRQ: u32 = 0x08;
+/// r/w: thread the interrupt is raised on
+pub const KBD_REG_IRQ_THREAD: u32 = 0x0C;
+/// r: number of events dropped because the buffer was full. any write resets it
+pub const KBD_REG_DROPPED: u32 = 0x10;
+/// size of the register block to map
+pub const KBD_REGS_SIZE: u32 = 0x14;
+
+/// enables `KBD_REG_IRQ`
+pub const KBD_IRQ_ENABLE: u32 = 1 << 31;
+
+/// the low 16 bits of an event: the unicode char of the key, or one of the `KEY_*` codes
+pub const KBD_KEY_MASK: u32 = 0xFFFF;
+pub const KBD_MOD_SHIFT: u32 = 1 << 16;
+pub const KBD_MOD_CTRL: u32 = 1 << 17;
+pub const KBD_MOD_ALT: u32 = 1 << 18;
+/// set for a key release, clear for a press
+pub const KBD_RELEASE: u32 = 1 << 31;
+
+pub const KEY_BACKSPACE: u32 = 0x08;
+pub const KEY_TAB: u32 = 0x09;
+pub const KEY_ENTER: u32 = 0x0A;
+pub const KEY_ESCAPE: u32 = 0x1B;
+// special keys are in the unicode private use area, so they never collide with a char
+pub const KEY_UP: u32 = 0xE000;
+pub const KEY_DOWN: u32 = 0xE001;
+pub const KEY_LEFT: u32 = 0xE002;
+pub const KEY_RIGHT: u32 = 0xE003;
+pub const KEY_HOME: u32 = 0xE004;
+pub const KEY_END: u32 = 0xE005;
+pub const KEY_PAGE_UP: u32 = 0xE006;
+pub const KEY_PAGE_DOWN: u32 = 0xE007;
+pub const KEY_INSERT: u32 = 0xE008;
+pub const KEY_DELETE: u32 = 0xE009;
+/// F1 to F12 are `KEY_F1 + 0` to `KEY_F1 + 11`
+pub const KEY_F1: u32 = 0xE010;
+
+/// Keyboard with a buffer of press and release events. Reading never blocks, an empty buffer reads as 0.
+/// The host injects events with `press`, `release`, `type_text` or `feed_ansi`, a full buffer drops new events.
+///
+/// Map the registers with `Machine::with_mmio_device`, `dev_read` takes the next event as well.
+/// Events pushed into a clone kept by the host reach the guest, also the ones pushed before the machine starts.
+#[derive(Clone)]
+pub struct Keyboard {
+    inner: Arc<Mutex<KeyboardState>>,
+}
+
+struct KeyboardState {
+    bus: Option<Bus>,
+    events: VecDeque<u32>,
+    capacity: usize,
+    dropped: u32,
+    irq: u32,
+    irq_thread: u32,
+    /// the cut off end of the input of `feed_ansi`
+    pending: Vec<u8>,
+}
+
+impl Default for Keyboard {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Keyboard {
+    /// keyboard buffering up to 64 events
+    pub fn new() -> Self {
+        Self::with_capacity(64)
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        let capacity = capacity.max(1);
+        Self { inner: Arc::new(Mutex::new(KeyboardState {
+            bus: None,
+            events: VecDeque::with_capacity(capacity),
+            capacity,
+            dropped: 0,
+            irq: 0,
+            irq_thread: 0,
+            pending: vec![],
+        })) }
+    }
+
+    /// queues a raw event, returns false if the buffer is full
+    pub fn push(&self, event: u32) -> bool {
+        self.state().push(event)
+    }
+
+    /// queues a press of key with `KBD_MOD_*` modifiers
+    pub fn press(&self, key: u32, modifiers: u32) -> bool {
+        self.push(key & KBD_KEY_MASK | modifiers & !KBD_KEY_MASK & !KBD_RELEASE)
+    }
+
+    /// queues a release of key with `KBD_MOD_*` modifiers
+    pub fn release(&self, key: u32, modifiers: u32) -> bool {
+        self.push(key & KBD_KEY_MASK | modifiers & !KBD_KEY_MASK | KBD_RELEASE)
+    }
+
+    /// queues a press and a release of key
+    pub fn tap(&self, key: u32, modifiers: u32) -> bool {
+        self.press(key, modifiers) && self.release(key, modifiers)
+    }
+
+    /// taps every char of text, `\n` as `KEY_ENTER`. chars which are no key are skipped, see `char_key`
+    pub fn type_text(&self, text: &str) -> bool {
+        text.chars().filter_map(char_key).all(|(key, modifiers)| self.tap(key, modifiers))
+    }
+
+    /// taps the keys of terminal input: chars, control chars as ctrl + letter and ansi escape sequences
+    /// of special keys with modifiers. unknown or broken sequences are skipped. an escape sequence or utf-8 char
+    /// which is cut off at the end is completed by the next call, a single escape at the end is the escape key
+    pub fn feed_ansi(&self, input: &[u8]) -> bool {
+        let mut bytes = std::mem::take(&mut self.state().pending);
+        bytes.extend_from_slice(input);
+        let (keys, rest) = parse_ansi(&bytes);
+        self.state().pending = rest;
+        keys.into_iter().all(|(key, modifiers)| self.tap(key, modifiers))
+    }
+
+    /// number of buffered events
+    pub fn len(&self) -> usize {
+        self.state().events.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.state().events.is_empty()
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, KeyboardState> {
+        self.inner.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// key and modifiers of a char from a terminal. None for chars which do not fit into the 16 bits of a key
+/// and for the private use area, which would be read as the special keys
+fn char_key(c: char) -> Option<(u32, u32)> {
+    Some(match c {
+        '\n' | '\r' => (KEY_ENTER, 0),
+        '\t' => (KEY_TAB, 0),
+        '\x08' | '\x7f' => (KEY_BACKSPACE, 0),
+        '\x1b' => (KEY_ESCAPE, 0),
+        // ctrl + a to ctrl + z
+        '\x01'..='\x1a' => ('a' as u32 + c as u32 - 1, KBD_MOD_CTRL),
+        c if c.is_ascii_uppercase() => (c as u32, KBD_MOD_SHIFT),
+        '\u{E000}'..='\u{F8FF}' | '\u{10000}'.. => None?,
+        c => (c as u32, 0),
+    })
+}
+
+/// the first char of input and its length, invalid utf-8 is U+FFFD. None if input ends within the char
+fn first_char(input: &[u8]) -> Option<(char, usize)> {
+    let head = &input[..input.len().min(4)];
+    let valid = match std::str::from_utf8(head) {
+        Ok(valid) => valid,
+        Err(e) if e.valid_up_to() > 0 => std::str::from_utf8(&head[..e.valid_up_to()]).ok()?,
+        Err(e) => return e.error_len().map(|len| (char::REPLACEMENT_CHARACTER, len)),
+    };
+    valid.chars().next().map(|c| (c, c.len_utf8()))
+}
+
+/// the keys of terminal input and the cut off escape sequence or char at its end
+fn parse_ansi(input: &[u8]) -> (Vec<(u32, u32)>, Vec<u8>) {
+    let mut keys = vec![];
+    let mut i = 0;
+    while i < input.len() {
+        let start = i;
+        let Some((c, len)) = first_char(&input[i..]) else { return (keys, input[start..].to_vec()) };
+        i += len;
+        let key = match (c, input.get(i)) {
+            ('\x1b', None) => Some((KEY_ESCAPE, 0)),
+            ('\x1b', Some(b'[')) => {
+                // CSI: parameter bytes, intermediate bytes, then a final byte
+                let body = &input[i + 1..];
+                let params = body.iter().take_while(|b| (0x30..=0x3F).contains(*b)).count();
+                let intermediates = body[params..].iter().take_while(|b| (0x20..=0x2F).contains(*b)).count();
+                match body.get(params + intermediates) {
+                    None => return (keys, input[start..].to_vec()),
+                    Some(end @ 0x40..=0x7E) => {
+                        i += 1 + params + intermediates + 1;
+                        let mut params = std::str::from_utf8(&body[..params]).unwrap_or("").split(';').map(|p| p.parse::<u32>().unwrap_or(0));
+                        let (n, m) = (params.next().unwrap_or(0), params.next().unwrap_or(1));
+                        csi_key(n, *end as char).filter(|_| intermediates == 0).map(|key| (key, ansi_modifiers(m)))
+                    },
+                    // not a sequence, the byte is read as input again
+                    Some(_) => {
+                        i += 1 + params + intermediates;
+                        None
+                    },
+                }
+            },
+            ('\x1b', Some(b'O')) => match input.get(i + 1) {
+                None => return (keys, input[start..].to_vec()),
+                Some(end) => {
+                    i += 2;
+                    match end {
+                        b'P'..=b'S' => Some((KEY_F1 + (end - b'P') as u32, 0)),
+                        end => csi_key(0, *end as char).map(|key| (key, 0)),
+                    }
+                },
+            },
+            // escape before a key is how terminals send alt
+            ('\x1b', Some(_)) => {
+                let Some((c, len)) = first_char(&input[i..]) else { return (keys, input[start..].to_vec()) };
+                i += len;
+                char_key(c).map(|(key, modifiers)| (key, modifiers | KBD_MOD_ALT))
+            },
+            (c, _) => char_key(c),
+        };
+        keys.extend(key);
+    }
+    (keys, vec![])
+}
+
+/// special key of a CSI sequence with the numeric parameter n and the final char
+fn csi_key(n: u32, end: char) -> Option<u32> {
+    Some(match (end, n) {
+        ('A', _) => KEY_UP,
+        ('B', _) => KEY_DOWN,
+        ('C', _) => KEY_RIGHT,
+        ('D', _) => KEY_LEFT,
+        ('H', _) | ('~', 1) | ('~', 7) => KEY_HOME,
+        ('F', _) | ('~', 4) | ('~', 8) => KEY_END,
+        ('P'..='S', _) => KEY_F1 + (end as u32 - 'P' as u32),
+        ('~', 2) => KEY_INSERT,
+        ('~', 3) => KEY_DELETE,
+        ('~', 5) => KEY_PAGE_UP,
+        ('~', 6) => KEY_PAGE_DOWN,
+        ('~', 11..=15) => KEY_F1 + (n - 11),
+        ('~', 17..=21) => KEY_F1 + 5 + (n - 17),
+        ('~', 23..=24) => KEY_F1 + 10 + (n - 23),
+        _ => None?
+    })
+}
+
+/// `KBD_MOD_*` of the xterm modifier parameter, which is 1 + shift 1, alt 2, ctrl 4
+fn ansi_modifiers(m: u32) -> u32 {
+    let m = m.saturating_sub(1);
+    let mut modifiers = 0;
+    if m & 1 != 0 { modifiers |= KBD_MOD_SHIFT; }
+    if m & 2 != 0 { modifiers |= KBD_MOD_ALT; }
+    if m & 4 != 0 { modifiers |= KBD_MOD_CTRL; }
+    modifiers
+}
+
+impl KeyboardState {
+    fn push(&mut self, event: u32) -> bool {
+        if self.events.len() >= self.capacity {
+            self.dropped = self.dropped.saturating_add(1);
+            return false;
+        }
+        self.events.push_back(event);
+        if self.irq & KBD_IRQ_ENABLE != 0 && let Some(bus) = &self.bus {
+            let _ = bus.raise_interrupt(self.irq_thread, self.irq & !KBD_IRQ_ENABLE);
+        }
+        true
+    }
+
+    fn load(&mut self, offset: u32) -> u32 {
+        match offset {
+            KBD_REG_DATA => self.events.pop_front().unwrap_or(0),
+            KBD_REG_COUNT => self.events.len() as u32,
+            KBD_REG_IRQ => self.irq,
+            KBD_REG_IRQ_THREAD => self.irq_thread,
+            KBD_REG_DROPPED => self.dropped,
+            _ => 0
+        }
+    }
+
+    fn store(&mut self, offset: u32, data: u32) {
+        match offset {
+            KBD_REG_IRQ => self.irq = data,
+            KBD_REG_IRQ_THREAD => self.irq_thread = data,
+            KBD_REG_DROPPED => self.dropped = 0,
+            _ => {}
+        }
+    }
+}
+
+impl Device for Keyboard {
+    fn read(&mut self) -> u32 {
+        self.state().load(KBD_REG_DATA)
+    }
+
+    /// the char of the next press event, releases and special keys are skipped. 0 if there is none
+    fn read8(&mut self) -> u8 {
+        let mut state = self.state();
+        while let Some(event) = state.events.pop_front() {
+            let key = event & KBD_KEY_MASK;
+            if event & KBD_RELEASE == 0 && key < 0x80 {
+                return key as u8;
+            }
+        }
+        0
+    }
+
+    fn write8(&mut self, _data: u8) {}
+
+    fn flush(&mut self) -> std::io::Result<()> { Ok(()) }
+
+    fn load8(&mut self, offset: u32) -> u8 {
+        // a byte of the data register would take the whole event, so only the other registers can be read bytewise
+        match offset & !3 {
+            KBD_REG_DATA => 0,
+            reg => (self.state().load(reg) >> ((offset & 3) * 8)) as u8
+        }
+    }
+
+    fn load(&mut self, offset: u32) -> u32 {
+        load_registers(self, offset, |device| device.state().load(offset))
+    }
+
+    fn store8(&mut self, offset: u32, data: u8) {
+        store8_registers(self, offset, data);
+    }
+
+    fn store(&mut self, offset: u32, data: u32) {
+        self.state().store(offset, data);
+    }
+
+    fn attach(&mut self, bus: Bus) {
+        self.state().bus = Some(bus);
+    }
+
+    fn reset(&mut self) {
+        let mut state = self.state();
+        state.dropped = 0;
+        state.irq = 0;
+        state.irq_thread = 0;
+    }
+}
@@ -0,0 +1,154 @@
+pub(crate) mod image;
+pub(crate) mod framebuffer;
+pub(crate) mod text;
+pub(crate) mod block;
+pub(crate) mod timer;
+pub(crate) mod clock;
+pub(crate) mod random;
+pub(crate) mod console;
+pub(crate) mod keyboard;
+pub(crate) mod audio;
+
+use std::{io::Read, sync::{Arc, Mutex, Weak}};
+
+use super::{MachineCtx, VmError, Scheduler};
+
+pub use self::console::{Console, SharedBuffer};
+
+/// device id of the console, which `write_stdout`, `read_stdin` and `flush_stdout` use
+pub const DEVICE_CONSOLE: u32 = 0;
+/// device id of the random number generator every machine starts with, see `Machine::with_seed`
+pub const DEVICE_RNG: u32 = 1;
+
+/// Operations on invalid device id return immediately, setting FLAG_BIT_E.
+/// A device should never panic, instead just return 0.
+pub trait Device: Send {
+    /// read from device. may block until data arrives
+    fn read(&mut self) -> u32 {
+        (self.read8() as u32) << 24 | (self.read8() as u32) << 16 | (self.read8() as u32) << 8 | (self.read8() as u32)
+    }
+    /// write to device
+    fn write(&mut self, data: u32) {
+        self.write8((data >> 24) as u8);
+        self.write8((data >> 16) as u8);
+        self.write8((data >> 8) as u8);
+        self.write8(data as u8);
+    }
+    /// read from device. may block until data arrives
+    fn read8(&mut self) -> u8;
+    /// write to device
+    fn write8(&mut self, data: u8);
+    /// an error sets FLAG_BIT_E
+    fn flush(&mut self) -> std::io::Result<()>;
+    /// the input `read8` waits on, for devices whose reads block. the machine reads it without holding the device,
+    /// so a thread waiting for input does not stall the other threads which use the device
+    fn blocking_input(&mut self) -> Option<Arc<Mutex<dyn Read + Send>>> { None }
+    /// load from a memory mapped register at offset bytes from the start of the mapped range
+    fn load8(&mut self, _offset: u32) -> u8 { 0 }
+    /// store to a memory mapped register at offset bytes from the start of the mapped range
+    fn store8(&mut self, _offset: u32, _data: u8) {}
+    /// load a word from memory mapped registers, little endian like ram
+    fn load(&mut self, offset: u32) -> u32 {
+        u32::from_le_bytes([self.load8(offset), self.load8(offset + 1), self.load8(offset + 2), self.load8(offset + 3)])
+    }
+    /// store a word to memory mapped registers, little endian like ram
+    fn store(&mut self, offset: u32, data: u32) {
+        for (i, b) in data.to_le_bytes().into_iter().enumerate() {
+            self.store8(offset + i as u32, b);
+        }
+    }
+    /// called once the device is added to a machine. keep the bus to raise interrupts or access memory later
+    fn attach(&mut self, _bus: Bus) {}
+    /// bring the device into its initial state, called before the machine starts running
+    fn reset(&mut self) {}
+    /// called once the virtual clock reached a tick requested with `Bus::wake_at`, now may be later than requested
+    fn tick(&mut self, _now: u64) {}
+}
+
+/// `Device::load` of a block of 32 bit registers: an aligned offset loads the register with `reg`,
+/// an unaligned one is put together from the bytes `Device::load8` returns, like in ram
+pub(crate) fn load_registers<D: Device + ?Sized>(device: &mut D, offset: u32, reg: impl FnOnce(&mut D) -> u32) -> u32 {
+    if offset & 3 != 0 {
+        return u32::from_le_bytes([device.load8(offset), device.load8(offset + 1), device.load8(offset + 2), device.load8(offset + 3)]);
+    }
+    reg(device)
+}
+
+/// `Device::store8` of a block of 32 bit registers: a byte stored at the start of a register acts like a word store of the byte,
+/// the other bytes are ignored
+pub(crate) fn store8_registers<D: Device + ?Sized>(device: &mut D, offset: u32, data: u8) {
+    if offset & 3 == 0 {
+        device.store(offset, data as u32);
+    }
+}
+
+/// Address range [start, end) whose loads and stores go to a device instead of ram
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct MmioRegion {
+    pub start: u32,
+    pub end: u32,
+    pub device: u32,
+}
+
+impl MmioRegion {
+    #[inline]
+    pub fn contains(&self, addr: u32) -> bool {
+        addr >= self.start && addr < self.end
+    }
+    #[inline]
+    pub fn overlaps(&self, start: u32, end: u32) -> bool {
+        start < self.end && end > self.start
+    }
+}
+
+/// Handle of a device to the machine it is attached to
+#[derive(Clone)]
+pub struct Bus {
+    machine: Weak<MachineCtx>,
+    device: u32,
+}
+
+impl Bus {
+    pub(crate) fn new(machine: &Arc<MachineCtx>, device: u32) -> Self {
+        Self { machine: Arc::downgrade(machine), device }
+    }
+
+    fn machine(&self) -> Result<Arc<MachineCtx>, VmError> {
+        self.machine.upgrade().ok_or(VmError::Detached)
+    }
+
+    /// raises an interrupt on a thread, see `Machine::raise_interrupt`
+    pub fn raise_interrupt(&self, thread_id: u32, vector: u32) -> Result<(), VmError> {
+        self.machine()?.raise_interrupt(thread_id, vector)
+    }
+
+    /// size of the ram of the machine in bytes, see `Machine::memory_size`
+    pub fn memory_size(&self) -> Result<u32, VmError> {
+        Ok(self.machine()?.memory.len() as u32)
+    }
+
+    pub fn read_memory(&self, addr: u32, buf: &mut [u8]) -> Result<(), VmError> {
+        self.machine()?.read_memory(addr, buf)
+    }
+
+    pub fn write_memory(&self, addr: u32, data: &[u8]) -> Result<(), VmError> {
+        self.machine()?.write_memory(addr, data)
+    }
+
+    /// current virtual time of the machine, see `Machine::ticks`
+    pub fn ticks(&self) -> Result<u64, VmError> {
+        Ok(self.machine()?.ticks.load(std::sync::atomic::Ordering::Relaxed))
+    }
+
+    /// whether the machine uses the deterministic scheduler. devices should not depend on host time then
+    pub fn deterministic(&self) -> Result<bool, VmError> {
+        Ok(matches!(self.machine()?.scheduler, Scheduler::Deterministic { .. }))
+    }
+
+    /// calls `Device::tick` of this device once the virtual clock reached tick.
+    /// every call requests a single wakeup, a tick in the past wakes on the next instruction
+    pub fn wake_at(&self, tick: u64) -> Result<(), VmError> {
+        self.machine()?.wake_at(self.device, tick);
+        Ok(())
+    }
+}
@@ -0,0 +1,92 @@
+use std::{collections::hash_map::RandomState, hash::{BuildHasher, Hasher}, time::SystemTime};
+
+use crate::machine::rng::XorShift;
+
+use super::{Device, load_registers, store8_registers};
+
+/// r: the next random word
+pub const RNG_REG_VALUE: u32 = 0x00;
+/// w: seeds the generator with the word, making the following values reproducible
+pub const RNG_REG_SEED: u32 = 0x04;
+/// size of the register block to map
+pub const RNG_REGS_SIZE: u32 = 0x08;
+
+/// Pseudo random number generator, seeded either by the host or from host entropy. Not cryptographically secure.
+///
+/// `dev_read` and `dev_read8` return the next random word or byte, `dev_write` seeds the generator.
+/// Map the registers with `Machine::with_mmio_device` to use it with `ld` and `st` instead.
+pub struct Rng {
+    /// None to take a new seed from host entropy on every reset
+    seed: Option<u64>,
+    rng: XorShift,
+}
+
+impl Rng {
+    /// the same values on every run
+    pub fn seeded(seed: u64) -> Self {
+        Self { seed: Some(seed), rng: XorShift::new(seed) }
+    }
+
+    /// different values on every run
+    pub fn from_entropy() -> Self {
+        Self { seed: None, rng: XorShift::new(host_entropy()) }
+    }
+}
+
+/// the hasher keys of the standard library are random per process, mixed with the time for different values per call
+fn host_entropy() -> u64 {
+    let mut hasher = RandomState::new().build_hasher();
+    if let Ok(time) = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
+        hasher.write_u128(time.as_nanos());
+    }
+    hasher.finish()
+}
+
+impl Device for Rng {
+    fn read(&mut self) -> u32 {
+        self.rng.next_u32()
+    }
+
+    fn write(&mut self, data: u32) {
+        self.rng = XorShift::new(data as u64);
+    }
+
+    fn read8(&mut self) -> u8 {
+        self.rng.next_u32() as u8
+    }
+
+    fn write8(&mut self, data: u8) {
+        self.rng = XorShift::new(data as u64);
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> { Ok(()) }
+
+    fn load(&mut self, offset: u32) -> u32 {
+        load_registers(self, offset, |device| match offset {
+            RNG_REG_VALUE => device.rng.next_u32(),
+            _ => 0
+        })
+    }
+
+    fn load8(&mut self, offset: u32) -> u8 {
+        // every byte of the value is a fresh one
+        match offset & !3 {
+            RNG_REG_VALUE => self.rng.next_u32() as u8,
+            _ => 0
+        }
+    }
+
+    fn store(&mut self, offset: u32, data: u32) {
+        if offset == RNG_REG_SEED {
+            self.write(data);
+        }
+    }
+
+    fn store8(&mut self, offset: u32, data: u8) {
+        store8_registers(self, offset, data);
+    }
+
+    fn reset(&mut self) {
+        self.rng = XorShift::new(self.seed.unwrap_or_else(host_entropy));
+    }
+}
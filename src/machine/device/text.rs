@@ -0,0 +1,317 @@
+use std::{io::Write, sync::{Arc, Mutex}};
+
+use crate::machine::VmError;
+
+use super::{Device, Bus, load_registers, store8_registers, image::Image};
+
+/// r/w: address of the cell buffer in ram
+pub const TEXT_REG_BASE: u32 = 0x00;
+/// r: number of columns
+pub const TEXT_REG_COLUMNS: u32 = 0x04;
+/// r: number of rows
+pub const TEXT_REG_ROWS: u32 = 0x08;
+/// r/w: cursor column
+pub const TEXT_REG_CURSOR_X: u32 = 0x0C;
+/// r/w: cursor row
+pub const TEXT_REG_CURSOR_Y: u32 = 0x10;
+/// r/w: attribute of chars written to `TEXT_REG_PUTC`
+pub const TEXT_REG_ATTR: u32 = 0x14;
+/// w: write a char at the cursor and advance it. handles \n, \r and backspace, scrolls at the bottom
+pub const TEXT_REG_PUTC: u32 = 0x18;
+/// w: scroll up by n rows
+pub const TEXT_REG_SCROLL: u32 = 0x1C;
+/// w: any write presents the cell buffer as the next frame
+pub const TEXT_REG_PRESENT: u32 = 0x20;
+/// size of the register block to map
+pub const TEXT_REGS_SIZE: u32 = 0x24;
+
+/// width and height of a glyph in pixels
+pub const GLYPH_SIZE: u32 = 8;
+
+/// 16x16 glyphs of 8x8 pixels, one byte per pixel, in ascii order
+const FONT: &[u8; 128 * 128] = include_bytes!("../../../examples/font.raw");
+
+/// the 16 colors of an attribute nibble
+const PALETTE: [[u8;3];16] = [
+    [0x00, 0x00, 0x00], [0x00, 0x00, 0xAA], [0x00, 0xAA, 0x00], [0x00, 0xAA, 0xAA],
+    [0xAA, 0x00, 0x00], [0xAA, 0x00, 0xAA], [0xAA, 0x55, 0x00], [0xAA, 0xAA, 0xAA],
+    [0x55, 0x55, 0x55], [0x55, 0x55, 0xFF], [0x55, 0xFF, 0x55], [0x55, 0xFF, 0xFF],
+    [0xFF, 0x55, 0x55], [0xFF, 0x55, 0xFF], [0xFF, 0xFF, 0x55], [0xFF, 0xFF, 0xFF],
+];
+
+/// ansi color of a palette index without the bright bit
+const ANSI_COLORS: [u8;8] = [0, 4, 2, 6, 1, 5, 3, 7];
+
+/// default attribute: light gray on black
+pub const TEXT_DEFAULT_ATTR: u8 = 0x07;
+
+/// Character cell display. Every cell is 2 bytes in ram: the char and its attribute,
+/// with the foreground color in the low and the background color in the high nibble.
+/// Writing `TEXT_REG_PRESENT` copies the cells out of ram, they can then be rendered with the bundled font or as ansi text.
+///
+/// Map the registers with `Machine::with_mmio_device`, `dev_write8` writes a char like `TEXT_REG_PUTC`.
+/// A clone kept by the host renders the screen the guest presented last with `frame_text`, `frame_ansi` or `save_png`.
+#[derive(Clone)]
+pub struct TextMode {
+    inner: Arc<Mutex<TextModeState>>,
+}
+
+struct TextModeState {
+    bus: Option<Bus>,
+    columns: u32,
+    rows: u32,
+    base: u32,
+    initial_base: u32,
+    cursor: (u32, u32),
+    attr: u8,
+    /// cells and cursor of the last presented frame
+    cells: Vec<u8>,
+    frame_cursor: (u32, u32),
+    frame: u32,
+    ansi_output: Option<Box<dyn Write + Send>>,
+}
+
+impl TextMode {
+    pub fn new(columns: u32, rows: u32) -> Self {
+        let (columns, rows) = (columns.max(1), rows.max(1));
+        Self { inner: Arc::new(Mutex::new(TextModeState {
+            bus: None,
+            columns,
+            rows,
+            base: 0,
+            initial_base: 0,
+            cursor: (0, 0),
+            attr: TEXT_DEFAULT_ATTR,
+            cells: blank_cells(columns, rows),
+            frame_cursor: (0, 0),
+            frame: 0,
+            ansi_output: None,
+        })) }
+    }
+
+    /// address of the cell buffer after a reset
+    pub fn with_base(self, addr: u32) -> Self {
+        {
+            let mut state = self.state();
+            state.base = addr;
+            state.initial_base = addr;
+        }
+        self
+    }
+
+    /// redraws every presented frame with ansi escape codes to out, usually stdout
+    pub fn with_ansi_output(self, out: Box<dyn Write + Send>) -> Self {
+        self.state().ansi_output = Some(out);
+        self
+    }
+
+    /// number of presented frames
+    pub fn frame_count(&self) -> u32 {
+        self.state().frame
+    }
+
+    /// the last presented frame rendered with the bundled font, the cursor cell is inverted
+    pub fn frame(&self) -> Image {
+        self.state().render()
+    }
+
+    /// the last presented frame as ansi text, one line per row
+    pub fn frame_ansi(&self) -> String {
+        self.state().render_ansi()
+    }
+
+    /// the chars of the last presented frame without attributes, one line per row
+    pub fn frame_text(&self) -> String {
+        let state = self.state();
+        state.cells.chunks(state.columns.max(1) as usize * 2)
+            .map(|row| row.chunks(2).map(|cell| printable(cell[0])).collect::<String>() + "\n")
+            .collect()
+    }
+
+    pub fn save_png<P: AsRef<std::path::Path>>(&self, path: P) -> Result<(), VmError> {
+        Ok(self.frame().save_png(path)?)
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, TextModeState> {
+        self.inner.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+fn blank_cells(columns: u32, rows: u32) -> Vec<u8> {
+    [b' ', TEXT_DEFAULT_ATTR].repeat(columns as usize * rows as usize)
+}
+
+fn printable(c: u8) -> char {
+    if (0x20..0x7F).contains(&c) { c as char } else { ' ' }
+}
+
+impl TextModeState {
+    /// None if the cell is beyond the end of the address space
+    fn cell_addr(&self, x: u32, y: u32) -> Option<u32> {
+        y.checked_mul(self.columns)?.checked_add(x)?.checked_mul(2)?.checked_add(self.base)
+    }
+
+    fn putc(&mut self, c: u8) {
+        let (mut x, mut y) = self.cursor;
+        match c {
+            b'\n' => { x = 0; y += 1; },
+            b'\r' => x = 0,
+            // backspace
+            8 => x = x.saturating_sub(1),
+            _ => {
+                if let Some(bus) = &self.bus && let Some(addr) = self.cell_addr(x.min(self.columns - 1), y.min(self.rows - 1)) {
+                    let _ = bus.write_memory(addr, &[c, self.attr]);
+                }
+                x += 1;
+                if x >= self.columns { x = 0; y += 1; }
+            }
+        }
+        if y >= self.rows {
+            self.scroll(y - self.rows + 1);
+            y = self.rows - 1;
+        }
+        self.cursor = (x, y);
+    }
+
+    fn scroll(&mut self, n: u32) {
+        let Some(bus) = &self.bus else { return };
+        let n = n.min(self.rows);
+        let row_len = self.columns as usize * 2;
+        let mut cells = vec![0u8; row_len * self.rows as usize];
+        if bus.read_memory(self.base, &mut cells).is_err() { return; }
+        cells.copy_within(row_len * n as usize.., 0);
+        let blank = [b' ', self.attr].repeat(self.columns as usize * n as usize);
+        let len = cells.len();
+        cells[len - blank.len()..].copy_from_slice(&blank);
+        let _ = bus.write_memory(self.base, &cells);
+    }
+
+    fn present(&mut self) {
+        if let Some(bus) = &self.bus {
+            let mut cells = vec![0u8; self.columns as usize * self.rows as usize * 2];
+            if bus.read_memory(self.base, &mut cells).is_ok() {
+                self.cells = cells;
+            }
+        }
+        self.frame_cursor = self.cursor;
+        self.frame = self.frame.wrapping_add(1);
+        // taken while rendering, which borrows all of self
+        if let Some(mut out) = self.ansi_output.take() {
+            let text = self.render_ansi();
+            // home, then the frame
+            let _ = write!(out, "\x1b[H{text}\x1b[{};{}H", self.frame_cursor.1 + 1, self.frame_cursor.0 + 1);
+            let _ = out.flush();
+            self.ansi_output = Some(out);
+        }
+    }
+
+    fn render(&self) -> Image {
+        let mut image = Image::new(self.columns * GLYPH_SIZE, self.rows * GLYPH_SIZE);
+        for (i, cell) in self.cells.chunks(2).enumerate() {
+            let (cx, cy) = (i as u32 % self.columns, i as u32 / self.columns);
+            let (mut fg, mut bg) = (PALETTE[(cell[1] & 0xF) as usize], PALETTE[(cell[1] >> 4) as usize]);
+            if (cx, cy) == self.frame_cursor {
+                std::mem::swap(&mut fg, &mut bg);
+            }
+            let (gx, gy) = ((cell[0] % 16) as u32 * GLYPH_SIZE, (cell[0] / 16) as u32 * GLYPH_SIZE);
+            for y in 0..GLYPH_SIZE {
+                for x in 0..GLYPH_SIZE {
+                    let set = FONT[((gy + y) * 128 + gx + x) as usize] != 0;
+                    image.set_pixel(cx * GLYPH_SIZE + x, cy * GLYPH_SIZE + y, if set { fg } else { bg });
+                }
+            }
+        }
+        image
+    }
+
+    fn render_ansi(&self) -> String {
+        let mut out = String::new();
+        for row in self.cells.chunks(self.columns.max(1) as usize * 2) {
+            let mut attr = None;
+            for cell in row.chunks(2) {
+                if attr != Some(cell[1]) {
+                    let (fg, bg) = (cell[1] & 0xF, cell[1] >> 4);
+                    let fg = if fg & 8 != 0 { 90 } else { 30 } + ANSI_COLORS[(fg & 7) as usize];
+                    let bg = if bg & 8 != 0 { 100 } else { 40 } + ANSI_COLORS[(bg & 7) as usize];
+                    out += &format!("\x1b[{fg};{bg}m");
+                    attr = Some(cell[1]);
+                }
+                out.push(printable(cell[0]));
+            }
+            out += "\x1b[0m\r\n";
+        }
+        out
+    }
+
+    fn load(&self, offset: u32) -> u32 {
+        match offset {
+            TEXT_REG_BASE => self.base,
+            TEXT_REG_COLUMNS => self.columns,
+            TEXT_REG_ROWS => self.rows,
+            TEXT_REG_CURSOR_X => self.cursor.0,
+            TEXT_REG_CURSOR_Y => self.cursor.1,
+            TEXT_REG_ATTR => self.attr as u32,
+            _ => 0
+        }
+    }
+
+    fn store(&mut self, offset: u32, data: u32) {
+        match offset {
+            TEXT_REG_BASE => self.base = data,
+            TEXT_REG_CURSOR_X => self.cursor.0 = data.min(self.columns - 1),
+            TEXT_REG_CURSOR_Y => self.cursor.1 = data.min(self.rows - 1),
+            TEXT_REG_ATTR => self.attr = data as u8,
+            TEXT_REG_PUTC => self.putc(data as u8),
+            TEXT_REG_SCROLL => self.scroll(data),
+            TEXT_REG_PRESENT => self.present(),
+            _ => {}
+        }
+    }
+}
+
+impl Device for TextMode {
+    fn read8(&mut self) -> u8 { 0 }
+
+    fn write8(&mut self, data: u8) {
+        self.state().putc(data);
+    }
+
+    fn write(&mut self, data: u32) {
+        self.state().putc(data as u8);
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.state().present();
+        Ok(())
+    }
+
+    fn load8(&mut self, offset: u32) -> u8 {
+        (self.state().load(offset & !3) >> ((offset & 3) * 8)) as u8
+    }
+
+    fn load(&mut self, offset: u32) -> u32 {
+        load_registers(self, offset, |device| device.state().load(offset))
+    }
+
+    fn store8(&mut self, offset: u32, data: u8) {
+        store8_registers(self, offset, data);
+    }
+
+    fn store(&mut self, offset: u32, data: u32) {
+        self.state().store(offset, data);
+    }
+
+    fn attach(&mut self, bus: Bus) {
+        self.state().bus = Some(bus);
+    }
+
+    fn reset(&mut self) {
+        let mut state = self.state();
+        state.base = state.initial_base;
+        state.cursor = (0, 0);
+        state.attr = TEXT_DEFAULT_ATTR;
+        state.cells = blank_cells(state.columns, state.rows);
+        state.frame_cursor = (0, 0);
+        state.frame = 0;
+    }
+}
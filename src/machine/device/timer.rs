@@ -0,0 +1,224 @@
+use std::{sync::{Arc, Mutex}, time::{Duration, Instant}};
+
+use super::{Device, Bus, load_registers, store8_registers};
+
+/// r/w: see `TIMER_CTRL_*`. a write with `TIMER_CTRL_ENABLE` (re)starts the timer with the current period
+pub const TIMER_REG_CTRL: u32 = 0x00;
+/// r/w: low word of the period, in ticks or nanoseconds
+pub const TIMER_REG_PERIOD_LO: u32 = 0x04;
+/// r/w: high word of the period
+pub const TIMER_REG_PERIOD_HI: u32 = 0x08;
+/// r: ticks or nanoseconds until the timer expires, saturated to 32 bits. 0 if it is disabled
+pub const TIMER_REG_REMAINING: u32 = 0x0C;
+/// r: number of expiries since the last acknowledge, saturated to 32 bits. any write acknowledges them
+pub const TIMER_REG_PENDING: u32 = 0x10;
+/// r/w: interrupt raised on expiry: bit 31 enables it, the low bits are the vector
+pub const TIMER_REG_IRQ: u32 = 0x14;
+/// r/w: thread the interrupt is raised on
+pub const TIMER_REG_IRQ_THREAD: u32 = 0x18;
+/// size of the register block to map
+pub const TIMER_REGS_SIZE: u32 = 0x1C;
+
+/// the timer is running. cleared when a one-shot timer expires
+pub const TIMER_CTRL_ENABLE: u32 = 1 << 0;
+/// restart with the same period on expiry instead of stopping
+pub const TIMER_CTRL_PERIODIC: u32 = 1 << 1;
+/// count host nanoseconds instead of virtual ticks. not reproducible, the deterministic scheduler does not wait for it
+pub const TIMER_CTRL_HOST_TIME: u32 = 1 << 2;
+
+/// enables `TIMER_REG_IRQ`
+pub const TIMER_IRQ_ENABLE: u32 = 1 << 31;
+
+/// Interval timer counting virtual ticks (see `Machine::ticks`) or host nanoseconds.
+/// On expiry it counts up `TIMER_REG_PENDING` and raises its interrupt, if enabled.
+/// A period of 0 counts as 1.
+///
+/// Map the registers with `Machine::with_mmio_device`.
+/// A clone kept by the host reads the expiries the guest did not acknowledge yet with `pending`.
+#[derive(Clone)]
+pub struct Timer {
+    inner: Arc<Mutex<TimerState>>,
+}
+
+struct TimerState {
+    bus: Option<Bus>,
+    ctrl: u32,
+    period: u64,
+    /// tick of the next expiry
+    deadline: u64,
+    /// host time of the next expiry
+    host_deadline: Instant,
+    /// changed whenever the timer is restarted or stopped, so stale wakeups and host threads know they are outdated
+    generation: u64,
+    pending: u32,
+    irq: u32,
+    irq_thread: u32,
+}
+
+impl Default for Timer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Timer {
+    pub fn new() -> Self {
+        Self { inner: Arc::new(Mutex::new(TimerState {
+            bus: None,
+            ctrl: 0,
+            period: 0,
+            deadline: 0,
+            host_deadline: Instant::now(),
+            generation: 0,
+            pending: 0,
+            irq: 0,
+            irq_thread: 0,
+        })) }
+    }
+
+    /// number of expiries the guest did not acknowledge yet
+    pub fn pending(&self) -> u32 {
+        self.state().pending
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, TimerState> {
+        lock(&self.inner)
+    }
+
+    fn start(&self) {
+        let mut state = self.state();
+        state.generation = state.generation.wrapping_add(1);
+        if state.ctrl & TIMER_CTRL_ENABLE == 0 { return; }
+        let period = state.period.max(1);
+        if state.ctrl & TIMER_CTRL_HOST_TIME == 0 {
+            let Some(bus) = state.bus.clone() else { return };
+            let Ok(now) = bus.ticks() else { return };
+            state.deadline = now.saturating_add(period);
+            let _ = bus.wake_at(state.deadline);
+        } else {
+            state.host_deadline = Instant::now() + Duration::from_nanos(period);
+            let (inner, generation) = (self.inner.clone(), state.generation);
+            std::thread::spawn(move || host_timer(inner, generation));
+        }
+    }
+}
+
+fn lock(inner: &Mutex<TimerState>) -> std::sync::MutexGuard<'_, TimerState> {
+    inner.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// sleeps until the host deadline, for as long as the timer was not restarted or detached from its machine
+fn host_timer(inner: Arc<Mutex<TimerState>>, generation: u64) {
+    loop {
+        let deadline = lock(&inner).host_deadline;
+        std::thread::sleep(deadline.saturating_duration_since(Instant::now()));
+        let mut state = lock(&inner);
+        if state.generation != generation || state.bus.as_ref().is_none_or(|bus| bus.ticks().is_err()) { return; }
+        state.expire();
+        if state.ctrl & TIMER_CTRL_ENABLE == 0 { return; }
+        let period = Duration::from_nanos(state.period.max(1));
+        // skip expiries the host was too slow for instead of catching up with a burst
+        state.host_deadline = (state.host_deadline + period).max(Instant::now());
+    }
+}
+
+impl TimerState {
+    fn expire(&mut self) {
+        self.pending = self.pending.saturating_add(1);
+        if self.ctrl & TIMER_CTRL_PERIODIC == 0 {
+            self.ctrl &= !TIMER_CTRL_ENABLE;
+            self.generation = self.generation.wrapping_add(1);
+        }
+        if self.irq & TIMER_IRQ_ENABLE == 0 { return; }
+        if let Some(bus) = &self.bus {
+            let _ = bus.raise_interrupt(self.irq_thread, self.irq & !TIMER_IRQ_ENABLE);
+        }
+    }
+
+    fn remaining(&self) -> u64 {
+        if self.ctrl & TIMER_CTRL_ENABLE == 0 { return 0; }
+        if self.ctrl & TIMER_CTRL_HOST_TIME != 0 {
+            return self.host_deadline.saturating_duration_since(Instant::now()).as_nanos().min(u64::MAX as u128) as u64;
+        }
+        let now = self.bus.as_ref().and_then(|bus| bus.ticks().ok()).unwrap_or(self.deadline);
+        self.deadline.saturating_sub(now)
+    }
+
+    fn load(&self, offset: u32) -> u32 {
+        match offset {
+            TIMER_REG_CTRL => self.ctrl,
+            TIMER_REG_PERIOD_LO => self.period as u32,
+            TIMER_REG_PERIOD_HI => (self.period >> 32) as u32,
+            TIMER_REG_REMAINING => self.remaining().min(u32::MAX as u64) as u32,
+            TIMER_REG_PENDING => self.pending,
+            TIMER_REG_IRQ => self.irq,
+            TIMER_REG_IRQ_THREAD => self.irq_thread,
+            _ => 0
+        }
+    }
+}
+
+impl Device for Timer {
+    fn read8(&mut self) -> u8 { 0 }
+
+    fn write8(&mut self, _data: u8) {}
+
+    fn flush(&mut self) -> std::io::Result<()> { Ok(()) }
+
+    fn load8(&mut self, offset: u32) -> u8 {
+        (self.state().load(offset & !3) >> ((offset & 3) * 8)) as u8
+    }
+
+    fn load(&mut self, offset: u32) -> u32 {
+        load_registers(self, offset, |device| device.state().load(offset))
+    }
+
+    fn store8(&mut self, offset: u32, data: u8) {
+        store8_registers(self, offset, data);
+    }
+
+    fn store(&mut self, offset: u32, data: u32) {
+        {
+            let mut state = self.state();
+            match offset {
+                TIMER_REG_CTRL => state.ctrl = data & (TIMER_CTRL_ENABLE | TIMER_CTRL_PERIODIC | TIMER_CTRL_HOST_TIME),
+                TIMER_REG_PERIOD_LO => state.period = state.period & !0xFFFF_FFFF | data as u64,
+                TIMER_REG_PERIOD_HI => state.period = state.period & 0xFFFF_FFFF | (data as u64) << 32,
+                TIMER_REG_PENDING => state.pending = 0,
+                TIMER_REG_IRQ => state.irq = data,
+                TIMER_REG_IRQ_THREAD => state.irq_thread = data,
+                _ => {}
+            }
+        }
+        if offset == TIMER_REG_CTRL {
+            self.start();
+        }
+    }
+
+    fn attach(&mut self, bus: Bus) {
+        self.state().bus = Some(bus);
+    }
+
+    fn reset(&mut self) {
+        let mut state = self.state();
+        state.ctrl = 0;
+        state.period = 0;
+        state.generation = state.generation.wrapping_add(1);
+        state.pending = 0;
+        state.irq = 0;
+        state.irq_thread = 0;
+    }
+
+    fn tick(&mut self, now: u64) {
+        let mut state = self.state();
+        let running = state.ctrl & (TIMER_CTRL_ENABLE | TIMER_CTRL_HOST_TIME) == TIMER_CTRL_ENABLE;
+        // wakeups of a restarted timer are still requested, only the one at the deadline counts
+        if !running || now < state.deadline { return; }
+        state.expire();
+        if state.ctrl & TIMER_CTRL_ENABLE == 0 { return; }
+        state.deadline = state.deadline.saturating_add(state.period.max(1)).max(now + 1);
+        if let Some(bus) = &state.bus {
+            let _ = bus.wake_at(state.deadline);
+        }
+    }
+}
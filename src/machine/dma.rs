@@ -0,0 +1,117 @@
+use std::{collections::VecDeque, sync::atomic::Ordering};
+
+use super::MachineCtx;
+
+/// descriptor: transfer mode, see `DMA_MEM_TO_*`
+pub const DMA_DESC_MODE: u32 = 0x00;
+/// descriptor: source address, or device id
+pub const DMA_DESC_SRC: u32 = 0x04;
+/// descriptor: destination address, or device id
+pub const DMA_DESC_DST: u32 = 0x08;
+/// descriptor: number of bytes
+pub const DMA_DESC_LEN: u32 = 0x0C;
+/// descriptor: written by the engine, see `DMA_STATUS_*`
+pub const DMA_DESC_STATUS: u32 = 0x10;
+/// descriptor: interrupt raised on the issuing thread on completion: bit 31 enables it, the low bits are the vector
+pub const DMA_DESC_IRQ: u32 = 0x14;
+/// size of a descriptor in bytes
+pub const DMA_DESC_SIZE: u32 = 0x18;
+
+/// copy between two ram ranges, which may overlap
+pub const DMA_MEM_TO_MEM: u32 = 0;
+/// write every byte of a ram range to a device like `dev_write8`
+pub const DMA_MEM_TO_DEVICE: u32 = 1;
+/// fill a ram range with bytes read from a device like `dev_read8`. a device which blocks stalls all transfers
+pub const DMA_DEVICE_TO_MEM: u32 = 2;
+
+/// the transfer is queued or running
+pub const DMA_STATUS_BUSY: u32 = 1;
+pub const DMA_STATUS_DONE: u32 = 2;
+/// the descriptor was rejected, nothing was transferred
+pub const DMA_STATUS_ERROR: u32 = 3;
+
+/// enables `DMA_DESC_IRQ`
+pub const DMA_IRQ_ENABLE: u32 = 1 << 31;
+
+/// number of transfers which can be queued at once
+pub const DMA_QUEUE_LEN: usize = 16;
+
+/// a checked transfer, all ranges are in ram and accessible by the issuing thread
+pub(crate) struct Transfer {
+    pub thread_id: u32,
+    pub desc: u32,
+    pub mode: u32,
+    pub src: u32,
+    pub dst: u32,
+    pub len: u32,
+    pub irq: u32,
+    pub done: u32,
+}
+
+/// Copies queued transfers in the background, a fixed number of bytes per tick of the virtual clock, one transfer after the other
+pub(crate) struct DmaEngine {
+    queue: VecDeque<Transfer>,
+    pub bytes_per_tick: u32,
+}
+
+impl Default for DmaEngine {
+    fn default() -> Self {
+        Self { queue: VecDeque::new(), bytes_per_tick: 4 }
+    }
+}
+
+impl Transfer {
+    /// ram to ram copies with the destination behind an overlapping source run back to front
+    fn backwards(&self) -> bool {
+        self.mode == DMA_MEM_TO_MEM && self.dst > self.src && self.dst - self.src < self.len
+    }
+}
+
+impl MachineCtx {
+    /// queues a transfer and marks it busy, false if the queue is full
+    pub(crate) fn submit_dma(&self, transfer: Transfer) -> bool {
+        let mut dma = self.dma.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if dma.queue.len() >= DMA_QUEUE_LEN { return false; }
+        let _ = self.write_memory(transfer.desc + DMA_DESC_STATUS, &DMA_STATUS_BUSY.to_le_bytes());
+        dma.queue.push_back(transfer);
+        self.dma_active.store(true, Ordering::Release);
+        true
+    }
+
+    /// advances the queued transfers by one tick
+    pub(crate) fn step_dma(&self) {
+        // another thread is already moving the transfers along
+        let Ok(mut dma) = self.dma.try_lock() else { return };
+        let mut budget = dma.bytes_per_tick;
+        while budget > 0 {
+            let Some(transfer) = dma.queue.front_mut() else { break };
+            let n = budget.min(transfer.len - transfer.done);
+            let offset = if transfer.backwards() { transfer.len - transfer.done - n } else { transfer.done } as usize;
+            let (src, dst, n_usize) = (transfer.src as usize + offset, transfer.dst as usize + offset, n as usize);
+            unsafe {
+                let memory = self.mem_mut();
+                match transfer.mode {
+                    DMA_MEM_TO_MEM => memory.copy_within(src..src + n_usize, dst),
+                    DMA_MEM_TO_DEVICE => { self.with_device(transfer.dst, |device| for b in &memory[src..src + n_usize] {
+                        device.write8(*b);
+                    }); },
+                    _ => { self.with_device(transfer.src, |device| for b in &mut memory[dst..dst + n_usize] {
+                        *b = device.read8();
+                    }); },
+                }
+            }
+            transfer.done += n;
+            budget -= n;
+            if transfer.done == transfer.len {
+                let transfer = dma.queue.pop_front().unwrap();
+                let _ = self.write_memory(transfer.desc + DMA_DESC_STATUS, &DMA_STATUS_DONE.to_le_bytes());
+                if transfer.irq & DMA_IRQ_ENABLE != 0 {
+                    let _ = self.raise_interrupt(transfer.thread_id, transfer.irq & !DMA_IRQ_ENABLE);
+                }
+            }
+        }
+        if dma.queue.is_empty() {
+            self.dma_active.store(false, Ordering::Release);
+        }
+    }
+}
@@ -0,0 +1,75 @@
+use std::{fmt::Display, any::Any};
+
+/// Errors of the vm itself. Errors of a guest program which it can handle itself only set FLAG_BIT_E.
+#[derive(Debug)]
+pub enum VmError {
+    /// reading the image failed
+    Io(std::io::Error),
+    /// the image does not fit into the supplied memory
+    ImageTooLarge { image_size: usize, memory_size: u32 },
+    /// the image can not be loaded
+    InvalidImage(String),
+    /// a guest thread crashed while executing
+    GuestFault { thread_id: u32, ip: u32, message: String },
+    /// no thread with this id exists
+    UnknownThread(u32),
+    /// the thread is executed by an os thread and can not be accessed from the host
+    ThreadRunning(u32),
+    /// no register with this index exists
+    InvalidRegister(u32),
+    /// no interrupt vector with this index exists
+    InvalidInterrupt(u32),
+    /// a memory access from the host is out of bounds
+    OutOfBounds { addr: u32, len: usize },
+    /// the machine of a device bus does not exist anymore
+    Detached,
+    /// a memory mapped range is empty, outside of memory or overlaps another one
+    InvalidMapping { start: u32, end: u32 },
+    /// with the deterministic scheduler every thread waits and neither a device nor the dma engine can wake them
+    Deadlock,
+}
+
+/// the message of a panic of a guest thread
+pub(crate) fn panic_message(panic: &(dyn Any + Send)) -> String {
+    if let Some(s) = panic.downcast_ref::<&str>() {
+        s.to_string()
+    } else if let Some(s) = panic.downcast_ref::<String>() {
+        s.clone()
+    } else {
+        "unknown fault".to_string()
+    }
+}
+
+impl Display for VmError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VmError::Io(e) => write!(f, "I/O error: {e}"),
+            VmError::ImageTooLarge { image_size, memory_size } => write!(f, "image too large: need at least 0x{image_size:X} bytes, only got 0x{memory_size:X} supplied"),
+            VmError::InvalidImage(reason) => write!(f, "invalid image: {reason}"),
+            VmError::GuestFault { thread_id, ip, message } => write!(f, "guest fault in thread {thread_id} at 0x{ip:08X}: {message}"),
+            VmError::UnknownThread(id) => write!(f, "unknown thread {id}"),
+            VmError::ThreadRunning(id) => write!(f, "thread {id} is running"),
+            VmError::InvalidRegister(reg) => write!(f, "invalid register 0x{reg:02X}"),
+            VmError::InvalidInterrupt(vector) => write!(f, "invalid interrupt vector {vector}"),
+            VmError::OutOfBounds { addr, len } => write!(f, "memory access of 0x{len:X} bytes at 0x{addr:08X} is out of bounds"),
+            VmError::Detached => write!(f, "device is not attached to a machine"),
+            VmError::InvalidMapping { start, end } => write!(f, "can not map device to 0x{start:08X}..0x{end:08X}"),
+            VmError::Deadlock => write!(f, "all threads wait and nothing can wake them"),
+        }
+    }
+}
+
+impl std::error::Error for VmError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            VmError::Io(e) => Some(e),
+            _ => None
+        }
+    }
+}
+
+impl From<std::io::Error> for VmError {
+    fn from(e: std::io::Error) -> Self {
+        VmError::Io(e)
+    }
+}
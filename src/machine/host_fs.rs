@@ -0,0 +1,159 @@
+use std::{collections::HashMap, fs::{File, OpenOptions}, io::{ErrorKind, Read, Seek, SeekFrom, Write}, path::{Component, Path, PathBuf}};
+
+use super::VmError;
+
+/// open for reading
+pub const OPEN_READ: u32 = 1 << 0;
+/// open for writing
+pub const OPEN_WRITE: u32 = 1 << 1;
+/// create the file if it does not exist
+pub const OPEN_CREATE: u32 = 1 << 2;
+/// truncate the file to 0 bytes
+pub const OPEN_TRUNCATE: u32 = 1 << 3;
+/// every write appends to the end
+pub const OPEN_APPEND: u32 = 1 << 4;
+
+/// `stat` kind of a file
+pub const STAT_FILE: u32 = 1;
+/// `stat` kind of a directory
+pub const STAT_DIR: u32 = 2;
+
+/// Error codes of syscalls, written to `%N`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SysError {
+    /// the file or directory does not exist
+    NotFound = 1,
+    /// the path leaves the root, or the host denied access
+    PermissionDenied = 2,
+    /// the file descriptor is not open
+    BadFd = 3,
+    /// invalid argument, like an invalid path or seek position
+    Invalid = 4,
+    /// any other host io error
+    Io = 5,
+    /// unknown syscall, no host filesystem or missing permission
+    NoSys = 6,
+    /// the file already exists
+    Exists = 7,
+    /// a buffer or path is outside of the accessible memory
+    Fault = 8,
+}
+
+impl From<std::io::Error> for SysError {
+    fn from(e: std::io::Error) -> Self {
+        match e.kind() {
+            ErrorKind::NotFound => SysError::NotFound,
+            ErrorKind::PermissionDenied => SysError::PermissionDenied,
+            ErrorKind::AlreadyExists => SysError::Exists,
+            ErrorKind::InvalidInput | ErrorKind::InvalidData => SysError::Invalid,
+            _ => SysError::Io
+        }
+    }
+}
+
+/// Host directory mounted as the root of the guest filesystem. Guest paths are resolved relative to the root,
+/// absolute or not, and may never leave it, neither by `..` nor through symlinks.
+pub struct HostFs {
+    root: PathBuf,
+    files: HashMap<u32, File>,
+    next_fd: u32,
+}
+
+impl HostFs {
+    pub fn new<P: AsRef<Path>>(root: P) -> Result<Self, VmError> {
+        let root = root.as_ref().canonicalize()?;
+        if !root.is_dir() {
+            return Err(VmError::Io(std::io::Error::new(ErrorKind::NotADirectory, format!("{} is not a directory", root.display()))));
+        }
+        // 0 is never a valid fd
+        Ok(Self { root, files: HashMap::new(), next_fd: 1 })
+    }
+
+    /// maps a guest path to a host path inside of the root
+    fn resolve(&self, path: &str) -> Result<PathBuf, SysError> {
+        let mut parts = vec![];
+        for component in Path::new(path).components() {
+            match component {
+                Component::Normal(part) => parts.push(part),
+                Component::ParentDir => { parts.pop().ok_or(SysError::PermissionDenied)?; },
+                Component::CurDir | Component::RootDir => {},
+                Component::Prefix(_) => Err(SysError::Invalid)?,
+            }
+        }
+        let path = parts.iter().fold(self.root.clone(), |path, part| path.join(part));
+        // symlinks may point anywhere, so check where the existing part of the path really is
+        let mut existing = path.as_path();
+        let mut rest = vec![];
+        while !existing.exists() {
+            // a dangling symlink would be followed when creating the file
+            if existing.symlink_metadata().is_ok() {
+                return Err(SysError::PermissionDenied);
+            }
+            rest.push(existing.file_name().ok_or(SysError::Invalid)?);
+            existing = existing.parent().ok_or(SysError::Invalid)?;
+        }
+        let real = existing.canonicalize()?;
+        if !real.starts_with(&self.root) {
+            return Err(SysError::PermissionDenied);
+        }
+        Ok(rest.iter().rev().fold(real, |path, part| path.join(part)))
+    }
+
+    pub fn open(&mut self, path: &str, flags: u32) -> Result<u32, SysError> {
+        let path = self.resolve(path)?;
+        let file = OpenOptions::new()
+            .read(flags & OPEN_READ != 0)
+            .write(flags & (OPEN_WRITE | OPEN_APPEND) != 0)
+            .create(flags & OPEN_CREATE != 0)
+            .truncate(flags & OPEN_TRUNCATE != 0)
+            .append(flags & OPEN_APPEND != 0)
+            .open(path)?;
+        let fd = self.next_fd;
+        self.next_fd = self.next_fd.checked_add(1).ok_or(SysError::Io)?;
+        self.files.insert(fd, file);
+        Ok(fd)
+    }
+
+    pub fn close(&mut self, fd: u32) -> Result<(), SysError> {
+        self.files.remove(&fd).map(|_| ()).ok_or(SysError::BadFd)
+    }
+
+    pub fn read(&mut self, fd: u32, buf: &mut [u8]) -> Result<u32, SysError> {
+        Ok(self.file(fd)?.read(buf)? as u32)
+    }
+
+    pub fn write(&mut self, fd: u32, data: &[u8]) -> Result<u32, SysError> {
+        Ok(self.file(fd)?.write(data)? as u32)
+    }
+
+    /// whence: 0 from the start, 1 from the current position, 2 from the end. returns the new position
+    pub fn seek(&mut self, fd: u32, offset: i32, whence: u32) -> Result<u32, SysError> {
+        let pos = match whence {
+            0 => SeekFrom::Start(u64::try_from(offset).map_err(|_| SysError::Invalid)?),
+            1 => SeekFrom::Current(offset as i64),
+            2 => SeekFrom::End(offset as i64),
+            _ => Err(SysError::Invalid)?
+        };
+        u32::try_from(self.file(fd)?.seek(pos)?).map_err(|_| SysError::Invalid)
+    }
+
+    /// size and kind (STAT_*) of a file or directory
+    pub fn stat(&self, path: &str) -> Result<(u32, u32), SysError> {
+        let meta = std::fs::metadata(self.resolve(path)?)?;
+        let kind = if meta.is_dir() { STAT_DIR } else { STAT_FILE };
+        Ok((meta.len().min(u32::MAX as u64) as u32, kind))
+    }
+
+    /// name of the nth entry of a directory, in sorted order
+    pub fn read_dir(&self, path: &str, index: u32) -> Result<String, SysError> {
+        let mut names = std::fs::read_dir(self.resolve(path)?)?
+            .map(|entry| entry.map(|entry| entry.file_name().to_string_lossy().into_owned()))
+            .collect::<Result<Vec<_>, _>>()?;
+        names.sort();
+        names.into_iter().nth(index as usize).ok_or(SysError::NotFound)
+    }
+
+    fn file(&mut self, fd: u32) -> Result<&mut File, SysError> {
+        self.files.get_mut(&fd).ok_or(SysError::BadFd)
+    }
+}
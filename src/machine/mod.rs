@@ -1,19 +1,68 @@
 pub(crate) mod thread;
 pub(crate) mod device;
+pub(crate) mod error;
+pub(crate) mod scheduler;
+pub(crate) mod rng;
+pub(crate) mod host_fs;
+pub(crate) mod dma;
 
-use std::{path::Path, fs::File, io::{Seek, Read}, collections::HashMap, sync::{Arc, atomic::{AtomicBool, AtomicU32, Ordering}}};
+use std::{path::Path, ops::Range, collections::HashMap, sync::{Arc, Mutex, RwLock, atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering}}};
 
-use self::thread::ThreadCore;
+use self::thread::{ThreadCore, NUM_REGS, REG_P};
+use self::device::{MmioRegion, DEVICE_CONSOLE, DEVICE_RNG, random::Rng, console::read_byte};
+use self::host_fs::HostFs;
+use self::dma::DmaEngine;
+pub use self::error::VmError;
+pub use self::scheduler::Scheduler;
+pub use self::device::{Device, Bus, Console, SharedBuffer};
 
 
 pub struct Machine {
     pub ctx: Arc<MachineCtx>
 }
 
+/// Why the main thread of a machine stopped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitReason {
+    /// the main thread executed `halt`
+    Halted,
+    /// the main thread was stopped without halting
+    Terminated,
+    /// the thread crashed with a fault the guest could not handle, see `Machine::guest_fault`
+    Faulted,
+}
+
+/// Final state of the main thread once the machine stopped
+#[derive(Debug, Clone)]
+pub struct ExitStatus {
+    /// exit code passed to `halt`, 0 if the thread did not halt
+    pub code: u32,
+    pub reason: ExitReason,
+    /// registers of the main thread at the time it stopped
+    pub registers: [u32;64],
+}
+
 pub struct MachineCtx {
-    pub memory: Box<Vec<u8>>,
+    pub memory: Vec<u8>,
+
+    pub threads: RwLock<HashMap<u32, Arc<ThreadCore>>>,
+
+    pub devices: HashMap<u32, Mutex<Box<dyn Device>>>,
+    pub(crate) mmio: Vec<MmioRegion>,
+    pub(crate) host_fs: Option<Mutex<HostFs>>,
 
-    pub threads: HashMap<u32, Arc<ThreadCore>>,
+    /// virtual time, advanced by every executed or retried instruction of any thread
+    pub(crate) ticks: AtomicU64,
+    /// earliest tick in `wakeups`, u64::MAX if there is none
+    pub(crate) next_wake: AtomicU64,
+    /// ticks at which devices asked to be woken, with their device id
+    pub(crate) wakeups: Mutex<Vec<(u64, u32)>>,
+    /// queued transfers of the `dma` instruction, advanced on every tick
+    pub(crate) dma: Mutex<DmaEngine>,
+    /// whether `dma` has queued transfers
+    pub(crate) dma_active: AtomicBool,
+
+    pub scheduler: Scheduler,
 
     pub running: AtomicBool,
     pub atomic_lock: AtomicBool,
@@ -22,48 +71,319 @@ pub struct MachineCtx {
 }
 
 impl MachineCtx {
+    pub(crate) fn thread(&self, id: u32) -> Option<Arc<ThreadCore>> {
+        self.threads.read().unwrap().get(&id).cloned()
+    }
+    /// runs f on a device, None if no device with this id exists
+    pub(crate) fn with_device<R>(&self, id: u32, f: impl FnOnce(&mut dyn Device) -> R) -> Option<R> {
+        let device = self.devices.get(&id)?;
+        // a device which panicked is still usable, it should never panic in the first place
+        let mut device = device.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        Some(f(device.as_mut()))
+    }
+    /// reads a byte, or with word a big endian word like `Device::read`, from a device.
+    /// a blocking input of the device is read without holding the device. None if no device with this id exists
+    pub(crate) fn read_device(&self, id: u32, word: bool) -> Option<u32> {
+        let Some(input) = self.with_device(id, |device| device.blocking_input())? else {
+            return self.with_device(id, |device| if word { device.read() } else { device.read8() as u32 });
+        };
+        let mut input = input.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let bytes = if word { 4 } else { 1 };
+        Some((0..bytes).fold(0, |value, _| value << 8 | read_byte(&mut *input) as u32))
+    }
+    /// advances the virtual clock by one tick and calls `Device::tick` on all devices which are due
+    #[inline]
+    pub(crate) fn tick(&self) {
+        let now = self.ticks.fetch_add(1, Ordering::Relaxed) + 1;
+        if now >= self.next_wake.load(Ordering::Acquire) {
+            self.wake_devices(now);
+        }
+        if self.dma_active.load(Ordering::Acquire) {
+            self.step_dma();
+        }
+    }
+    /// advances the virtual clock to the next device wakeup while all threads wait.
+    /// false if neither a device nor the dma engine is going to do anything on their own
+    pub(crate) fn skip_to_wakeup(&self) -> bool {
+        if self.dma_active.load(Ordering::Acquire) { return true; }
+        let next = self.next_wake.load(Ordering::Acquire);
+        if next == u64::MAX { return false; }
+        let now = self.ticks.fetch_max(next, Ordering::Relaxed).max(next);
+        self.wake_devices(now);
+        true
+    }
+    pub(crate) fn wake_at(&self, device: u32, tick: u64) {
+        let mut wakeups = self.wakeups.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        wakeups.push((tick, device));
+        self.next_wake.fetch_min(tick, Ordering::AcqRel);
+    }
+    fn wake_devices(&self, now: u64) {
+        let mut due = vec![];
+        {
+            let mut wakeups = self.wakeups.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            wakeups.retain(|&(tick, device)| {
+                if tick > now { return true; }
+                due.push(device);
+                false
+            });
+            self.next_wake.store(wakeups.iter().map(|(tick, _)| *tick).min().unwrap_or(u64::MAX), Ordering::Release);
+        }
+        // devices may ask for the next wakeup from within tick, so the list is not locked anymore
+        due.sort();
+        due.dedup();
+        for device in due {
+            self.with_device(device, |device| device.tick(now));
+        }
+    }
+    pub(crate) fn raise_interrupt(&self, thread_id: u32, vector: u32) -> Result<(), VmError> {
+        let thread = self.thread(thread_id).ok_or(VmError::UnknownThread(thread_id))?;
+        if !thread.raise_interrupt(vector) { return Err(VmError::InvalidInterrupt(vector)); }
+        Ok(())
+    }
+    pub(crate) fn read_memory(&self, addr: u32, buf: &mut [u8]) -> Result<(), VmError> {
+        let range = self.memory_range(addr, buf.len())?;
+        buf.copy_from_slice(&self.memory[range]);
+        Ok(())
+    }
+    pub(crate) fn write_memory(&self, addr: u32, data: &[u8]) -> Result<(), VmError> {
+        let range = self.memory_range(addr, data.len())?;
+        unsafe { self.mem_mut()[range].copy_from_slice(data); }
+        Ok(())
+    }
+    fn memory_range(&self, addr: u32, len: usize) -> Result<std::ops::Range<usize>, VmError> {
+        let start = addr as usize;
+        match start.checked_add(len) {
+            Some(end) if end <= self.memory.len() => Ok(start..end),
+            _ => Err(VmError::OutOfBounds { addr, len })
+        }
+    }
+    /// ids of all threads, in ascending order
+    pub(crate) fn thread_ids(&self) -> Vec<u32> {
+        let mut ids = self.threads.read().unwrap().keys().copied().collect::<Vec<_>>();
+        ids.sort();
+        ids
+    }
     #[inline]
     #[allow(clippy::mut_from_ref)]
-    pub(crate) unsafe  fn mem_mut<'a>(&'a self) -> &'a mut Vec<u8> {
+    pub(crate) unsafe fn mem_mut(&self) -> &mut Vec<u8> {
         #[allow(invalid_reference_casting)]
-        unsafe { &mut *(&*self.memory as *const Vec<u8> as *mut Vec<u8>) }
+        unsafe { &mut *(&self.memory as *const Vec<u8> as *mut Vec<u8>) }
     }
     #[inline]
     #[allow(clippy::mut_from_ref)]
     pub(crate) unsafe fn mutator(&self) -> &mut Self {
         #[allow(invalid_reference_casting)]
-        &mut *(self as *const _ as *mut _)
+        unsafe { &mut *(self as *const _ as *mut _) }
     }
 }
 
 impl Machine {
-    pub fn from_image<P: AsRef<Path>>(path: P, memory_size: u32) -> Self {
-        let mut image = File::open(path).unwrap();
-        let img_size = image.stream_len().unwrap() as usize;
-        if memory_size < img_size as u32 {
-            panic!("need at least 0x{:X} bytes, only got 0x{:X} supplied", img_size, memory_size)
+    pub fn from_image<P: AsRef<Path>>(path: P, memory_size: u32) -> Result<Self, VmError> {
+        Self::from_bytes(&std::fs::read(path)?, memory_size)
+    }
+
+    pub fn from_bytes(image: &[u8], memory_size: u32) -> Result<Self, VmError> {
+        if image.is_empty() {
+            return Err(VmError::InvalidImage("image is empty".to_string()));
         }
-        let mut memory = Box::new(Vec::with_capacity(memory_size as usize));
-        image.read_to_end(&mut memory).unwrap();
-        // zero initialize the rest
-        for _ in memory.len()..memory_size as usize {
-            memory.push(0);
+        if image.len() > memory_size as usize {
+            return Err(VmError::ImageTooLarge { image_size: image.len(), memory_size });
         }
+        let mut memory = Vec::with_capacity(memory_size as usize);
+        memory.extend_from_slice(image);
+        // zero initialize the rest
+        memory.resize(memory_size as usize, 0);
         let ctx = Arc::new(MachineCtx { 
             memory, 
             threads: Default::default(),
+            devices: Default::default(),
+            mmio: Default::default(),
+            host_fs: None,
+            ticks: AtomicU64::new(0),
+            next_wake: AtomicU64::new(u64::MAX),
+            wakeups: Default::default(),
+            dma: Default::default(),
+            dma_active: AtomicBool::new(false),
+            scheduler: Scheduler::Threaded,
             running: AtomicBool::new(true), 
             thread_count: AtomicU32::new(0), 
             next_thead_id: AtomicU32::new(0),
             atomic_lock: AtomicBool::new(false),
         });
-        Machine {
+        ThreadCore::create_main(&ctx);
+        Ok(Machine {
             ctx
+        }.with_device(DEVICE_CONSOLE, Box::new(Console::new()))
+            .with_device(DEVICE_RNG, Box::new(Rng::from_entropy())))
+    }
+
+    /// Adds a device at the given id, replacing the device which used it before.
+    /// Panics if a guest thread still runs on its own os thread, which happens once the main thread stopped without halting
+    pub fn with_device(mut self, id: u32, mut device: Box<dyn Device>) -> Self {
+        device.attach(Bus::new(&self.ctx, id));
+        self.ctx_mut().devices.insert(id, Mutex::new(device));
+        self
+    }
+
+    /// Replaces the console at `DEVICE_CONSOLE`, for example to script input or capture output
+    pub fn with_console(self, console: Console) -> Self {
+        self.with_device(DEVICE_CONSOLE, Box::new(console))
+    }
+
+    /// Seeds the random number generator at `DEVICE_RNG`, so guests get the same random values on every run
+    pub fn with_seed(self, seed: u64) -> Self {
+        self.with_device(DEVICE_RNG, Box::new(Rng::seeded(seed)))
+    }
+
+    /// Sets how many bytes the dma engine moves per tick, at least 1. Defaults to 4
+    pub fn with_dma_rate(self, bytes_per_tick: u32) -> Self {
+        self.ctx.dma.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).bytes_per_tick = bytes_per_tick.max(1);
+        self
+    }
+
+    /// Adds a device at the given id and maps the address range to it. Loads and stores of guest threads in the range
+    /// go to the device instead of ram. The range has to be inside of memory and may not overlap another mapped range.
+    /// Panics like `with_device` while guest threads run.
+    pub fn with_mmio_device(mut self, id: u32, range: Range<u32>, device: Box<dyn Device>) -> Result<Self, VmError> {
+        let Range { start, end } = range;
+        if start >= end || end > self.memory_size() || self.ctx.mmio.iter().any(|region| region.overlaps(start, end)) {
+            return Err(VmError::InvalidMapping { start, end });
+        }
+        self.ctx_mut().mmio.push(MmioRegion { start, end, device: id });
+        Ok(self.with_device(id, device))
+    }
+
+    /// Mounts a host directory as the root of the filesystem guests access with `sys`
+    pub fn with_host_fs<P: AsRef<Path>>(mut self, root: P) -> Result<Self, VmError> {
+        let fs = HostFs::new(root)?;
+        self.ctx_mut().host_fs = Some(Mutex::new(fs));
+        Ok(self)
+    }
+
+    /// Resets all devices, `run` does this before starting the machine
+    pub fn reset_devices(&self) {
+        for id in self.ctx.devices.keys() {
+            self.ctx.with_device(*id, |device| device.reset());
         }
     }
 
-    pub fn run(mut self) {
-        ThreadCore::launch_main(&mut self);
+    pub fn with_scheduler(mut self, scheduler: Scheduler) -> Self {
+        self.ctx_mut().scheduler = scheduler;
+        self
+    }
+
+    /// Runs the machine until the main thread terminates. The other threads can still be inspected afterwards
+    pub fn run(&self) -> Result<ExitStatus, VmError> {
+        let main = self.thread(0)?;
+        self.reset_devices();
+        match self.ctx.scheduler {
+            Scheduler::Threaded => {
+                // a panic of the main thread is recorded as its fault
+                if let Some(handle) = main.clone().start() {
+                    let _ = handle.join();
+                }
+            },
+            Scheduler::Deterministic { time_slice, seed } => scheduler::run_deterministic(&self.ctx, time_slice, seed)?,
+        }
+        main.guest_fault().map_or(Ok(main.exit_status()), Err)
+    }
+
+    /// Executes up to `n` instructions of a thread on the calling os thread.
+    /// Returns the number of executed instructions, which is less than `n` if the thread stopped or is waiting.
+    pub fn step(&self, thread_id: u32, n: u32) -> Result<u32, VmError> {
+        let thread = self.idle_thread(thread_id)?;
+        std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| thread.step(n))).map_err(|panic| {
+            thread.terminate();
+            thread.fault(panic)
+        })
+    }
+
+    /// Virtual time of the machine: the number of instructions all threads executed or retried while waiting,
+    /// plus the ticks the deterministic scheduler skipped while all threads waited
+    pub fn ticks(&self) -> u64 {
+        self.ctx.ticks.load(Ordering::Relaxed)
+    }
+
+    /// Ids of all threads of this machine, in ascending order. Threads joined by their parent are gone
+    pub fn thread_ids(&self) -> Vec<u32> {
+        self.ctx.thread_ids()
+    }
+
+    /// Exit status of a thread, `None` while it has not stopped yet. A thread which crashed stops with `ExitReason::Faulted`
+    pub fn exit_status(&self, thread_id: u32) -> Result<Option<ExitStatus>, VmError> {
+        let thread = self.thread(thread_id)?;
+        Ok(thread.is_stopped().then(|| thread.exit_status()))
+    }
+
+    /// The fault which crashed a thread, like a double fault. `None` if it did not crash
+    pub fn guest_fault(&self, thread_id: u32) -> Result<Option<VmError>, VmError> {
+        Ok(self.thread(thread_id)?.guest_fault())
+    }
+
+    pub fn registers(&self, thread_id: u32) -> Result<[u32;64], VmError> {
+        Ok(self.thread(thread_id)?.registers)
+    }
+
+    pub fn register(&self, thread_id: u32, reg: u32) -> Result<u32, VmError> {
+        if reg >= NUM_REGS { return Err(VmError::InvalidRegister(reg)); }
+        Ok(self.thread(thread_id)?.registers[reg as usize])
+    }
+
+    /// Sets a register of a thread which is not running on its own os thread
+    pub fn set_register(&self, thread_id: u32, reg: u32, value: u32) -> Result<(), VmError> {
+        if reg >= NUM_REGS { return Err(VmError::InvalidRegister(reg)); }
+        let thread = self.idle_thread(thread_id)?;
+        unsafe { thread.mutator().registers[reg as usize] = value; }
+        if reg == REG_P { thread.flush_tlb(); }
+        Ok(())
+    }
+
+    /// Raises an interrupt on a thread, it is delivered once the thread has interrupts enabled and the vector unmasked
+    pub fn raise_interrupt(&self, thread_id: u32, vector: u32) -> Result<(), VmError> {
+        self.ctx.raise_interrupt(thread_id, vector)
+    }
+
+    pub fn memory_size(&self) -> u32 {
+        self.ctx.memory.len() as u32
+    }
+
+    pub fn read_memory(&self, addr: u32, buf: &mut [u8]) -> Result<(), VmError> {
+        self.ctx.read_memory(addr, buf)
+    }
+
+    pub fn write_memory(&self, addr: u32, data: &[u8]) -> Result<(), VmError> {
+        self.ctx.write_memory(addr, data)
+    }
+
+    pub fn read_u32(&self, addr: u32) -> Result<u32, VmError> {
+        let mut buf = [0u8;4];
+        self.read_memory(addr, &mut buf)?;
+        Ok(u32::from_le_bytes(buf))
+    }
+
+    pub fn write_u32(&self, addr: u32, value: u32) -> Result<(), VmError> {
+        self.write_memory(addr, &value.to_le_bytes())
+    }
+
+    /// the context for the builder methods, guest threads read it without locks.
+    /// waits for threads which are stopping and panics if one still runs on its own os thread
+    fn ctx_mut(&mut self) -> &mut MachineCtx {
+        if !self.ctx.running.load(Ordering::Acquire) {
+            while self.ctx.thread_count.load(Ordering::Relaxed) > 0 { std::thread::yield_now() }
+        }
+        assert_eq!(self.ctx.thread_count.load(Ordering::Acquire), 0, "a machine can not be changed while its guest threads run");
+        unsafe { self.ctx.mutator() }
+    }
+
+    fn thread(&self, thread_id: u32) -> Result<Arc<ThreadCore>, VmError> {
+        self.ctx.thread(thread_id).ok_or(VmError::UnknownThread(thread_id))
+    }
+
+    /// a thread which is not executed by an os thread and can be modified from the host
+    fn idle_thread(&self, thread_id: u32) -> Result<Arc<ThreadCore>, VmError> {
+        let thread = self.thread(thread_id)?;
+        if self.ctx.scheduler == Scheduler::Threaded && thread.is_running() { return Err(VmError::ThreadRunning(thread_id)); }
+        Ok(thread)
     }
 }
 
@@ -71,5 +391,8 @@ impl Drop for Machine {
     fn drop(&mut self) {
         self.ctx.running.store(false, Ordering::Release);
         while self.ctx.thread_count.load(Ordering::Relaxed) > 0 { std::thread::yield_now() }
+        // threads hold the context and the context holds the threads, the devices are only dropped once this cycle is gone.
+        // no os thread runs a guest thread anymore, a running one would hold its own reference
+        self.ctx.threads.write().unwrap_or_else(|poisoned| poisoned.into_inner()).clear();
     }
 }
\ No newline at end of file
@@ -0,0 +1,27 @@
+/// Small xorshift pseudo random number generator. Not cryptographically secure.
+#[derive(Debug, Clone)]
+pub(crate) struct XorShift {
+    state: u64
+}
+
+impl XorShift {
+    pub(crate) fn new(seed: u64) -> Self {
+        // splitmix64 so similar seeds diverge quickly and 0 is a valid seed
+        let mut z = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^= z >> 31;
+        Self { state: if z == 0 { 0x9E37_79B9_7F4A_7C15 } else { z } }
+    }
+
+    pub(crate) fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        self.state
+    }
+
+    pub(crate) fn next_u32(&mut self) -> u32 {
+        (self.next_u64() >> 32) as u32
+    }
+}
@@ -0,0 +1,58 @@
+use std::sync::Arc;
+
+use super::{MachineCtx, VmError, rng::XorShift};
+
+/// How guest threads are mapped onto host threads
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Scheduler {
+    /// every guest thread runs on its own os thread
+    #[default]
+    Threaded,
+    /// all guest threads are interleaved round-robin on the os thread calling `Machine::run`.
+    /// A thread runs up to `time_slice` instructions per turn. With a nonzero `seed` the length of each turn
+    /// is picked pseudo randomly between 1 and `time_slice`, which is reproducible for the same seed.
+    /// While every thread waits the virtual clock skips ahead to the next device wakeup. Without one `Machine::run` fails
+    /// with `VmError::Deadlock`, input from other host threads like host time timers is not waited for.
+    Deterministic { time_slice: u32, seed: u64 },
+}
+
+/// runs all started threads in order of their id until the main thread stops.
+/// once every thread waits the virtual clock skips to the next device wakeup, without one nothing can wake them anymore
+pub(crate) fn run_deterministic(machine: &Arc<MachineCtx>, time_slice: u32, seed: u64) -> Result<(), VmError> {
+    let time_slice = time_slice.max(1);
+    let mut rng = XorShift::new(seed);
+    let main = machine.thread(0).expect("main thread exists");
+    main.clone().start();
+    // whether every thread waited in the last round and nothing was left to skip to
+    let mut idle = false;
+    while !main.is_stopped() {
+        let mut waiting = true;
+        for id in machine.thread_ids() {
+            let Some(thread) = machine.thread(id) else { continue };
+            // terminating threads get one more turn to become terminated
+            if thread.is_ready() || thread.is_stopped() { continue; }
+            let slice = if seed == 0 { time_slice } else { rng.next_u32() % time_slice + 1 };
+            match std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| thread.step(slice))) {
+                Ok(executed) => waiting &= executed == 0 && !thread.is_stopped(),
+                Err(panic) => {
+                    // faults of other threads are kept for `Machine::guest_fault`
+                    let fault = thread.fault(panic);
+                    thread.terminate();
+                    if id == 0 {
+                        return Err(fault);
+                    }
+                    waiting = false;
+                }
+            }
+        }
+        if !waiting || machine.skip_to_wakeup() {
+            idle = false;
+        } else if idle {
+            return Err(VmError::Deadlock);
+        } else {
+            // the last tick of the round may have raised an interrupt, like a dma transfer which just completed
+            idle = true;
+        }
+    }
+    Ok(())
+}
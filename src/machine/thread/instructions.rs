@@ -14,14 +14,14 @@ macro_rules! define_vars {
         define_var!($i, $is, $isv, $doc, $len-1);
     };
     ($i: ident, $is: ident, $isv: ident, $d: literal, $( $instr: ident, $instr_str: ident, $instr_str_val: ident, $doc: literal, )* $len: literal) => {
-        define_var!($i, $is, $isv, $d, $len-${count(instr, 0)}-1);
+        define_var!($i, $is, $isv, $d, $len-${count($instr, 0)}-1);
         define_vars!($( $instr, $instr_str, $instr_str_val, $doc, )* $len);
     }
 }
 
 macro_rules! define_instructions {
     (regs $a: ident, $b: ident, $c: ident; context $self: ident; $(instr $instr: ident { $instr_str: ident = $instr_str_val: ident; $action: expr; $doc: literal; })*) => {
-        define_vars!($( $instr, $instr_str, $instr_str_val, $doc, )* ${count(instr, 0)});
+        define_vars!($( $instr, $instr_str, $instr_str_val, $doc, )* ${count($instr, 0)});
         macro_rules! impl_instructions_match {
             ($pass_self: ident, $ins: ident, $pass_a: ident, $pass_b: ident, $pass_c: ident) => { {
                 let $a = $pass_a;
@@ -92,14 +92,24 @@ macro_rules! some_or_default {
     };
 }
 
+/// the register a result is written to, None for results which only go to flags
+macro_rules! result_reg {
+    (write to reg $reg: ident $($rest: tt)*) => { Some($reg) };
+    ($($other: tt)*) => { None };
+}
+
+/// a page fault while reading the arguments or reserving the stack slots of the results stops the instruction
+/// before it has any effect, it is restarted once the fault is handled
 macro_rules! impl_func {
     ($self: ident || $expr: expr => ($($ret: ident: $ret_ty: ident => [$($ret_handle: ident)*]),*)) => { {
+        if !$self.results_writable(&[$( result_reg!($($ret_handle)*) ),*]) { return $self.restart_faulted(); }
         let ( $( $ret ),* ) = $expr;
         $( impl_arith_ret!($self, $ret, $ret_ty, $($ret_handle)*); )*
     } };
     ($self: ident |$($arg:ident: $arg_ty: ident $(as $alias: ident)?),* $(+ $carry: ident: $carry_ty: ident)?| $expr: expr => ($($ret: ident: $ret_ty: ident => [$($ret_handle: ident)*]),*)) => { {
         $( let some_or_default!($($alias)?, $arg) = unsafe { std::mem::transmute::<u32, $arg_ty>($self.read_arg($arg)) }; )*
         $( let $carry = carry_handler!($carry_ty $self.registers[REG_C as usize] => in); )?
+        if $self.fault.is_some() || !$self.results_writable(&[$( result_reg!($($ret_handle)*) ),*]) { return $self.restart_faulted(); }
         let ( $( $ret ),* ) = $expr;
         $( impl_arith_ret!($self, $ret, $ret_ty, $($ret_handle)*); )*
     } }
@@ -108,16 +118,19 @@ macro_rules! impl_func {
 macro_rules! impl_jump {
     ($self: ident jump $a: ident) => { {
         let a = $self.read_arg($a);
+        if $self.fault.is_some() { return $self.restart_faulted(); }
         unsafe {  $self.mutator().registers[REG_I as usize] = a; }
     } };
     ($self: ident jump $a: ident if $mask: ident) => { {
         let a = $self.read_arg($a);
+        if $self.fault.is_some() { return $self.restart_faulted(); }
         if $self.registers[REG_F as usize] & $mask != 0 {
             unsafe {  $self.mutator().registers[REG_I as usize] = a; }
         }
     } };
     ($self: ident jump $a: ident unless $mask: ident) => { {
         let a = $self.read_arg($a);
+        if $self.fault.is_some() { return $self.restart_faulted(); }
         if $self.registers[REG_F as usize] & $mask == 0 {
             unsafe {  $self.mutator().registers[REG_I as usize] = a; }
         }
@@ -132,9 +145,13 @@ pub(crate) use impl_arith_ret;
 pub(crate) use carry_handler; 
 pub(crate) use impl_jump; 
 pub(crate) use some_or_default;
+pub(crate) use result_reg;
 
+#[allow(dead_code)]
 pub(crate) type MaybeU32 = Option<u32>;
+#[allow(dead_code)]
 pub(crate) type MaybeI32 = Option<i32>;
+#[allow(dead_code)]
 pub(crate) type MaybeF32 = Option<f32>;
 
 define_instructions! {
@@ -143,7 +160,7 @@ define_instructions! {
     instr INSTR_NOOP { INSTR_NOOP_STR = noop; (); "no-op instruction"; }
     instr INSTR_ADD { INSTR_ADD_STR = add; impl_func!(thread |a: u32, b: u32| u32::overflowing_add(a, b) => (r: u32 => [write to reg c], o: bool => [carry])); "u32: a + b = c + carry"; }
     instr INSTR_SUB { INSTR_SUB_STR = sub; impl_func!(thread |a: u32, b: u32| u32::overflowing_sub(a, b) => (r: u32 => [write to reg c], o: bool => [carry])); "u32: a - b = c + carry"; }
-    instr INSTR_MUL { INSTR_MUL_STR = mul; impl_func!(thread |a: u32, b: u32| u32::widening_mul(a, b) => (r: u32 => [write to reg c], o: u32 => [carry])); "u32: a * b = c + carry"; }
+    instr INSTR_MUL { INSTR_MUL_STR = mul; impl_func!(thread |a: u32, b: u32| u32::carrying_mul(a, b, 0) => (r: u32 => [write to reg c], o: u32 => [carry])); "u32: a * b = c + carry"; }
     instr INSTR_DIV { INSTR_DIV_STR = div; impl_func!(thread |a: u32, b: u32| u32::checked_div(a, b) => (r: MaybeU32 => [write to reg c as u32 and on error FLAG_BIT_L])); "u32: a / b = c, FLAG_BIT_L if b == 0"; }
     instr INSTR_REM { INSTR_REM_STR = rem; impl_func!(thread |a: u32, b: u32| u32::checked_rem(a, b) => (r: MaybeU32 => [write to reg c as u32 and on error FLAG_BIT_L])); "u32: a % b = c, FLAG_BIT_L if b == 0"; }
     instr INSTR_POW { INSTR_POW_STR = pow; impl_func!(thread |a: u32, b: u32| u32::checked_pow(a, b) => (r: MaybeU32 => [write to reg c as u32 and on error FLAG_BIT_C])); "u32: a ** b = c, FLAG_BIT_C if overflow"; }
@@ -256,6 +273,8 @@ define_instructions! {
         let mutor = thread.mutator();
         //println!("{:?}", mutor.registers);
         let addr = mutor.read_arg(a);
+        let sp = mutor.registers[REG_S as usize];
+        if thread.fault.is_some() || !thread.writable(&[sp + 4, sp + 8]) { return thread.restart_faulted(); }
         mutor.registers[REG_S as usize] += 4;
         let base = mutor.registers[REG_S as usize];
         mutor.write_u32(mutor.registers[REG_S as usize], mutor.registers[REG_I as usize]);
@@ -310,9 +329,9 @@ define_instructions! {
     instr INSTR_JNL { INSTR_JNL_STR = jnl; impl_jump!(thread jump a unless FLAG_BIT_L); "jump if FLAG_BIT_L is unset"; }
     instr INSTR_CLLF { INSTR_CLLF_STR = cllf; impl_jump!(thread clear FLAG_BIT_L); "clear FLAG_BIT_L"; }
 
-    instr INSTR_WRITE_STDOUT { INSTR_WRITET_STDOUT_STR = write_stdout; impl_func!(thread |a: u32| print!("{}", char::from_u32(a).unwrap_or_else(|| unsafe { thread.mutator().registers[REG_F as usize] |= FLAG_BIT_E; '�' })) => ()); "u32: print char to stdout, flushes on newline (\\n). Sets FLAG_BIT_E on invalid char"; }
-    instr INSTR_FLUSH_STDOUT { INSTR_FLUSH_STDOUT_STR = flush_stdout; std::io::stdout().flush().unwrap_or_else(|_| unsafe { thread.mutator().registers[REG_F as usize] |= FLAG_BIT_E; }); "Flush stdout. Sets FLAG_BIT_E if errors while flushing."; }
-    instr INSTR_READ_STDIN { INSTR_READ_STDIN_STR = read_stdin; impl_func!(thread || getch::Getch::new().getch().unwrap_or_else(|_| unsafe { thread.mutator().registers[REG_F as usize] |= FLAG_BIT_E; 0 }) as u32 => (r: u32 => [write to reg a])); "Wait for a char on stdin. Sets FLAG_BIT_E if errors while getting."; }
+    instr INSTR_WRITE_STDOUT { INSTR_WRITET_STDOUT_STR = write_stdout; impl_func!(thread |a: u32| thread.write_console_char(a) => ()); "u32: print char to the console (device 0) as utf-8. Sets FLAG_BIT_E on invalid char"; }
+    instr INSTR_FLUSH_STDOUT { INSTR_FLUSH_STDOUT_STR = flush_stdout; thread.flush_device(DEVICE_CONSOLE); "Flush the console (device 0). Sets FLAG_BIT_E if flushing failed."; }
+    instr INSTR_READ_STDIN { INSTR_READ_STDIN_STR = read_stdin; impl_func!(thread || thread.read_device(DEVICE_CONSOLE, false) => (r: MaybeU32 => [write to reg a as u32 and on error FLAG_BIT_E])); "Wait for a char on the console (device 0)."; }
 
     // note: memory instructions follow the order convention of `instr source destination`
     instr INSTR_LD { INSTR_LD_STR = ld; impl_func!(thread |a: u32| thread.read_u32(a) => (r: u32 => [write to reg b])); "load source_addr dest_reg_or_stack"; }
@@ -325,6 +344,7 @@ define_instructions! {
         unsafe {
             let mutor = thread.mutator();
             let v = mutor.read_u32(mutor.registers[REG_S as usize]);
+            if thread.fault.is_some() || !thread.writable(&[mutor.registers[REG_S as usize] + 4]) { return thread.restart_faulted(); }
             mutor.registers[REG_S as usize] += 4;
             thread.write_u32(mutor.registers[REG_S as usize], v);
         }
@@ -336,6 +356,8 @@ define_instructions! {
             let a = mutor.read_u32(mutor.registers[REG_S as usize] - 8);
             let b = mutor.read_u32(mutor.registers[REG_S as usize] - 4);
             let c = mutor.read_u32(mutor.registers[REG_S as usize]);
+            let sp = mutor.registers[REG_S as usize];
+            if thread.fault.is_some() || !thread.writable(&[sp - 8, sp - 4, sp]) { return thread.restart_faulted(); }
             mutor.write_u32(mutor.registers[REG_S as usize] - 8, c);
             mutor.write_u32(mutor.registers[REG_S as usize] - 4, a);
             mutor.write_u32(mutor.registers[REG_S as usize], b);
@@ -347,6 +369,8 @@ define_instructions! {
             let a = mutor.read_u32(mutor.registers[REG_S as usize] - 8);
             let b = mutor.read_u32(mutor.registers[REG_S as usize] - 4);
             let c = mutor.read_u32(mutor.registers[REG_S as usize]);
+            let sp = mutor.registers[REG_S as usize];
+            if thread.fault.is_some() || !thread.writable(&[sp - 8, sp - 4, sp]) { return thread.restart_faulted(); }
             mutor.write_u32(mutor.registers[REG_S as usize] - 8, b);
             mutor.write_u32(mutor.registers[REG_S as usize] - 4, c);
             mutor.write_u32(mutor.registers[REG_S as usize], a);
@@ -356,8 +380,53 @@ define_instructions! {
     instr INSTR_BREAKPOINT { INSTR_BREAKPOINT_STR = breakpoint; { 
         println!();
         for i in 40..50 {
-            println!(" {:2} | {:10} | 0x{:08X} | {}", i, thread.registers[i], thread.registers[i], unsafe { std::mem::transmute::<u32, f32>(thread.registers[i]) }); 
+            println!(" {:2} | {:10} | 0x{:08X} | {}", i, thread.registers[i], thread.registers[i], f32::from_bits(thread.registers[i])); 
         }
         std::io::stdin().read_line(&mut String::new()).unwrap(); 
     }; "debug breakpoint"; }
-}
\ No newline at end of file
+
+    instr INSTR_HALT { INSTR_HALT_STR = halt; impl_func!(thread |a: u32| thread.halt(a) => ()); "u32: stop the current thread with exit code a. Halting the main thread stops the machine"; }
+
+    instr INSTR_SPAWN { INSTR_SPAWN_STR = spawn; impl_func!(thread |a: u32, b: u32| thread.spawn(a, b) => (r: MaybeU32 => [write to reg c as u32 and on error FLAG_BIT_E])); "spawn entry_addr stack_addr child_id: create a ready child thread, inheriting access range and permissions. FLAG_BIT_E without PERM_BIT_SPAWN"; }
+    instr INSTR_FORK { INSTR_FORK_STR = fork; impl_func!(thread |a: u32| thread.fork(a, b) => (r: MaybeU32 => [write to reg b as u32 and on error FLAG_BIT_E])); "fork stack_addr child_id: create a ready child thread with a copy of all registers and a new stack. child_id is 0 in the child, it has to be a register other than %I, %B and %S. FLAG_BIT_E without PERM_BIT_SPAWN or another child_id"; }
+    instr INSTR_TCH_START { INSTR_TCH_START_STR = tch_start; impl_func!(thread |a: u32| thread.start_child(a) => ()); "tch_start child_id: start a ready child thread, FLAG_BIT_E if it is not a ready descendant or without PERM_BIT_SPAWN"; }
+    instr INSTR_TCH_RANGE { INSTR_TCH_RANGE_STR = tch_range; impl_func!(thread |a: u32, b: u32, c: u32| thread.narrow_child(a, b, c) => ()); "tch_range child_id min_addr max_addr: narrow the access range of a ready child thread to [min, max), FLAG_BIT_E if not within the own range or without PERM_BIT_MEM_RANGE"; }
+    instr INSTR_TCH_MODPR { INSTR_TCH_MODPR_STR = tch_modpr; impl_func!(thread |a: u32, b: u32, c: u32| thread.set_child_pr(a, b, c) => ()); "tch_modpr child_id pr value: set permission register pr (PR_*) of a ready descendant or the current thread, FLAG_BIT_E if not or that would grant more than the own permissions/range"; }
+    instr INSTR_TCH_GETPR { INSTR_TCH_GETPR_STR = tch_getpr; impl_func!(thread |a: u32, b: u32| thread.get_child_pr(a, b) => (r: MaybeU32 => [write to reg c as u32 and on error FLAG_BIT_E])); "tch_getpr child_id pr dest: read permission register pr (PR_*) of a descendant, FLAG_BIT_E if not a descendant"; }
+    instr INSTR_T_SIG { INSTR_T_SIG_STR = t_sig; impl_func!(thread |a: u32, b: u32| thread.send_signal(a, b) => ()); "t_sig child_id bits: set signal bits in the t_sig register of a descendant, FLAG_BIT_E if not a descendant"; }
+    instr INSTR_SIG_POLL { INSTR_SIG_POLL_STR = sig_poll; impl_func!(thread || thread.poll_signal() => (r: u32 => [write to reg a])); "sig_poll dest: take all pending signal bits, 0 if there are none"; }
+    instr INSTR_SIG_WAIT { INSTR_SIG_WAIT_STR = sig_wait; {
+        if !thread.results_writable(&[Some(a)]) { return thread.restart_faulted(); }
+        if let Some(bits) = thread.wait_signal() { thread.write_arg(a, bits) }
+    }; "sig_wait dest: wait until there are pending signal bits and take them"; }
+    instr INSTR_SIG_RET { INSTR_SIG_RET_STR = sig_ret; thread.return_from_signal(); "return from the signal handler at %H, restoring I, F and C. E outside of the signal handler"; }
+    instr INSTR_T_STATE { INSTR_T_STATE_STR = t_state; impl_func!(thread |a: u32| thread.child_state(a) => (r: MaybeU32 => [write to reg b as u32 and on error FLAG_BIT_E])); "t_state child_id dest: state of a descendant: 0 ready, 1 running, 2 terminating, 3 terminated. FLAG_BIT_E if not a descendant"; }
+    instr INSTR_T_KILL { INSTR_T_KILL_STR = t_kill; impl_func!(thread |a: u32| thread.kill_child(a) => ()); "t_kill child_id: request termination of a descendant, a ready one is terminated immediately. FLAG_BIT_E if not a descendant"; }
+    instr INSTR_T_JOIN { INSTR_T_JOIN_STR = t_join; {
+        let child = thread.read_arg(a);
+        if thread.fault.is_some() || !thread.results_writable(&[Some(b)]) { return thread.restart_faulted(); }
+        thread.join_child(child, b)
+    }; "t_join child_id dest: wait until a descendant is terminated and get its exit code, 0 if it did not halt. a child joined by its parent is removed. FLAG_BIT_E if not a descendant or the current thread"; }
+
+    instr INSTR_ACAS { INSTR_ACAS_STR = acas; impl_func!(thread |a: u32, b: u32, c: u32| thread.atomic_cas(a, b, c) => ()); "acas addr expected new: atomically store new at addr if it contains expected, FLAG_BIT_Z if stored. FLAG_BIT_E without PERM_BIT_ATOMIC"; }
+    instr INSTR_AADD { INSTR_AADD_STR = aadd; impl_func!(thread |a: u32, b: u32| thread.atomic_op(a, |old| old.wrapping_add(b)) => (r: MaybeU32 => [write to reg c as u32 and on error FLAG_BIT_E])); "aadd addr value dest: atomically add value to the word at addr, dest is the old value. FLAG_BIT_E without PERM_BIT_ATOMIC"; }
+    instr INSTR_ASUB { INSTR_ASUB_STR = asub; impl_func!(thread |a: u32, b: u32| thread.atomic_op(a, |old| old.wrapping_sub(b)) => (r: MaybeU32 => [write to reg c as u32 and on error FLAG_BIT_E])); "asub addr value dest: atomically subtract value from the word at addr, dest is the old value. FLAG_BIT_E without PERM_BIT_ATOMIC"; }
+    instr INSTR_AAND { INSTR_AAND_STR = aand; impl_func!(thread |a: u32, b: u32| thread.atomic_op(a, |old| old & b) => (r: MaybeU32 => [write to reg c as u32 and on error FLAG_BIT_E])); "aand addr value dest: atomically and the word at addr with value, dest is the old value. FLAG_BIT_E without PERM_BIT_ATOMIC"; }
+    instr INSTR_AOR { INSTR_AOR_STR = aor; impl_func!(thread |a: u32, b: u32| thread.atomic_op(a, |old| old | b) => (r: MaybeU32 => [write to reg c as u32 and on error FLAG_BIT_E])); "aor addr value dest: atomically or the word at addr with value, dest is the old value. FLAG_BIT_E without PERM_BIT_ATOMIC"; }
+    instr INSTR_AXCHG { INSTR_AXCHG_STR = axchg; impl_func!(thread |a: u32, b: u32| thread.atomic_op(a, |_| b) => (r: MaybeU32 => [write to reg c as u32 and on error FLAG_BIT_E])); "axchg addr value dest: atomically replace the word at addr with value, dest is the old value. FLAG_BIT_E without PERM_BIT_ATOMIC"; }
+
+    instr INSTR_RETI { INSTR_RETI_STR = reti; thread.return_from_interrupt(); "return from an interrupt handler, restoring I, F and C"; }
+    instr INSTR_EI { INSTR_EI_STR = ei; unsafe { thread.mutator().registers[REG_F as usize] |= FLAG_BIT_I; }; "enable interrupts: set FLAG_BIT_I"; }
+    instr INSTR_DI { INSTR_DI_STR = di; unsafe { thread.mutator().registers[REG_F as usize] &= !FLAG_BIT_I; }; "disable interrupts: clear FLAG_BIT_I"; }
+    instr INSTR_INT { INSTR_INT_STR = int; impl_func!(thread |a: u32| if !thread.raise_interrupt(a) { thread.set_error() } => ()); "int vector: raise an interrupt on the current thread, FLAG_BIT_E if vector >= 32"; }
+    instr INSTR_WFI { INSTR_WFI_STR = wfi; thread.wait_for_interrupt(); "wait until an unmasked interrupt is pending. if interrupts are enabled its handler returns behind the wfi"; }
+
+    instr INSTR_DEV_READ { INSTR_DEV_READ_STR = dev_read; impl_func!(thread |a: u32| thread.read_device(a, true) => (r: MaybeU32 => [write to reg b as u32 and on error FLAG_BIT_E])); "dev_read device_id dest: read a word from a device, may block. FLAG_BIT_E on an invalid device id or without PERM_BIT_DEVICE"; }
+    instr INSTR_DEV_WRITE { INSTR_DEV_WRITE_STR = dev_write; impl_func!(thread |a: u32, b: u32| thread.device(a, |dev| dev.write(b)).unwrap_or_else(|| thread.set_error()) => ()); "dev_write device_id value: write a word to a device. FLAG_BIT_E on an invalid device id or without PERM_BIT_DEVICE"; }
+    instr INSTR_DEV_READ8 { INSTR_DEV_READ8_STR = dev_read8; impl_func!(thread |a: u32| thread.read_device(a, false) => (r: MaybeU32 => [write to reg b as u32 and on error FLAG_BIT_E])); "dev_read8 device_id dest: read a byte from a device, may block. FLAG_BIT_E on an invalid device id or without PERM_BIT_DEVICE"; }
+    instr INSTR_DEV_WRITE8 { INSTR_DEV_WRITE8_STR = dev_write8; impl_func!(thread |a: u32, b: u32| thread.device(a, |dev| dev.write8(b as u8)).unwrap_or_else(|| thread.set_error()) => ()); "dev_write8 device_id value: write the low byte of value to a device. FLAG_BIT_E on an invalid device id or without PERM_BIT_DEVICE"; }
+    instr INSTR_DEV_FLUSH { INSTR_DEV_FLUSH_STR = dev_flush; impl_func!(thread |a: u32| thread.flush_device(a) => ()); "dev_flush device_id: flush a device. FLAG_BIT_E if flushing failed, on an invalid device id or without PERM_BIT_DEVICE"; }
+    instr INSTR_SYS { INSTR_SYS_STR = sys; impl_func!(thread |a: u32| thread.syscall(a) => ()); "sys n: syscall n (SYS_*) with args in %0..%3 and the result in %0. FLAG_BIT_E and error code in %N on failure"; }
+    instr INSTR_DMA { INSTR_DMA_STR = dma; impl_func!(thread |a: u32| thread.start_dma(a) => ()); "dma desc_addr: queue the transfer of the descriptor at desc_addr (DMA_DESC_*) in the background, the status word becomes DMA_STATUS_DONE once it is done. FLAG_BIT_E if it is invalid or the queue is full"; }
+    instr INSTR_TLBFLUSH { INSTR_TLBFLUSH_STR = tlbflush; thread.flush_tlb(); "drop the cached page table entries of the current thread, needed after changing the page table at %P"; }
+}
@@ -1,16 +1,31 @@
 use super::ThreadCore;
 use super::instructions::*;
-use crate::machine::thread::{FLAG_BIT_L, FLAG_BIT_Z, FLAG_BIT_C, FLAG_BIT_S, FLAG_BIT_E, FLAG_PLACE_C, REG_I, REG_C, REG_F, REG_S, REG_B};
-use std::io::Write;
+use crate::machine::thread::{FLAG_BIT_L, FLAG_BIT_Z, FLAG_BIT_C, FLAG_BIT_S, FLAG_BIT_E, FLAG_BIT_I, FLAG_PLACE_C, REG_I, REG_C, REG_F, REG_S, REG_B};
+use crate::machine::device::DEVICE_CONSOLE;
 
 impl ThreadCore {
-    #[allow(unused)]
+    // the operand macros transmute generically, which includes u32 to u32
+    #[allow(unused, unnecessary_transmutes, clippy::useless_transmute)]
     pub(crate) fn exec_instr(&self) {
-        let (instr, a, b, c) = Self::split_instr(self.read_u32(self.registers[REG_I as usize]));
+        self.deliver_interrupt();
+        self.deliver_signal();
+        // entering a handler crashed the thread
+        if self.should_stop() { return; }
+        unsafe {
+            let mutor = self.mutator();
+            mutor.waiting_for_interrupt = false;
+            mutor.instr_addr = self.registers[REG_I as usize];
+            mutor.instr_stack = self.registers[REG_S as usize];
+            mutor.blocked = false;
+        }
+        self.begin_instr();
+        let (instr, a, b, c) = Self::split_instr(self.fetch_u32(self.registers[REG_I as usize]));
+        if self.fault.is_some() { return self.restart_faulted(); }
         //let instr_map = instr_id_name_map();
         //println!("{:?} {:?} {:?} {:?} {:?}", instr, instr_map.get(&instr), a, b, c);
         self.advance_ip();
         impl_instructions_match!(self, instr, a, b, c);
+        if self.fault.is_some() { self.restart_faulted(); }
         //println!("{:?}", self.registers);
      }
 }
@@ -0,0 +1,61 @@
+use std::sync::atomic::Ordering;
+
+use super::{ThreadCore, REG_I, REG_F, REG_V, REG_M, FLAG_BIT_I, NUM_INTERRUPTS};
+
+impl ThreadCore {
+    /// marks an interrupt vector as pending, it is delivered once interrupts are enabled and the vector is not masked.
+    /// returns false for an invalid vector
+    pub(crate) fn raise_interrupt(&self, vector: u32) -> bool {
+        if vector >= NUM_INTERRUPTS { return false; }
+        self.interrupts.fetch_or(1 << vector, Ordering::AcqRel);
+        true
+    }
+
+    /// pending interrupts which are not masked
+    #[inline]
+    fn unmasked_interrupts(&self) -> u32 {
+        self.interrupts.load(Ordering::Acquire) & self.registers[REG_M as usize]
+    }
+
+    /// jumps to the handler of the lowest pending unmasked vector if interrupts are enabled.
+    /// I, F and C are saved on the stack and restored by `reti`, interrupts are disabled in the handler.
+    /// a vector with a handler address of 0 or a table entry outside of the accessible memory is discarded
+    #[inline]
+    pub(crate) fn deliver_interrupt(&self) {
+        if self.registers[REG_F as usize] & FLAG_BIT_I == 0 { return; }
+        let pending = self.unmasked_interrupts();
+        if pending == 0 { return; }
+        let vector = pending.trailing_zeros();
+        self.interrupts.fetch_and(!(1 << vector), Ordering::AcqRel);
+        let table_entry = self.registers[REG_V as usize].wrapping_add(vector * 4);
+        if !self.check_trap_frame(Some(table_entry)) { return; }
+        // the interrupted code must not see FLAG_BIT_E because of the table
+        let handler = self.peek_u32(table_entry).unwrap_or(0);
+        if handler == 0 { return; }
+        unsafe {
+            let mutor = self.mutator();
+            if mutor.waiting_for_interrupt {
+                // return behind the `wfi`
+                mutor.registers[REG_I as usize] += 4;
+                mutor.waiting_for_interrupt = false;
+            }
+            mutor.push_trap_frame();
+            mutor.registers[REG_F as usize] &= !FLAG_BIT_I;
+            mutor.registers[REG_I as usize] = handler;
+        }
+    }
+
+    /// returns from an interrupt handler. sets FLAG_BIT_E instead if the trap frame is the one of the signal handler
+    pub(crate) fn return_from_interrupt(&self) {
+        if self.at_signal_frame() { return self.set_error(); }
+        self.pop_trap_frame();
+    }
+
+    /// waits until an unmasked interrupt is pending
+    pub(crate) fn wait_for_interrupt(&self) {
+        if self.unmasked_interrupts() == 0 {
+            self.block();
+            unsafe { self.mutator().waiting_for_interrupt = true; }
+        }
+    }
+}
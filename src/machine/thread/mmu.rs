@@ -0,0 +1,217 @@
+use super::{ThreadCore, REG_P, REG_A, REG_R, REG_S, REG_F, REG_M, FLAG_BIT_I, PERM_BIT_SUPERVISOR};
+
+/// size and alignment of a page, the low 12 bits of an address are the offset in its page
+pub const PAGE_SIZE: u32 = 0x1000;
+/// page directory and page table entries: the page is mapped
+pub const PTE_PRESENT: u32 = 1 << 0;
+/// page table entries: the page can be read
+pub const PTE_READ: u32 = 1 << 1;
+/// page table entries: the page can be written
+pub const PTE_WRITE: u32 = 1 << 2;
+/// page table entries: instructions can be executed from the page
+pub const PTE_EXECUTE: u32 = 1 << 3;
+/// page table entries: threads without PERM_BIT_SUPERVISOR can access the page
+pub const PTE_USER: u32 = 1 << 4;
+/// page directory and page table entries: physical address of the page table or page
+pub const PTE_FRAME: u32 = !(PAGE_SIZE - 1);
+
+/// interrupt vector raised on a page fault
+pub const VECTOR_PAGE_FAULT: u32 = 0;
+/// %R: the fault happened while reading
+pub const FAULT_READ: u32 = 1;
+/// %R: the fault happened while writing
+pub const FAULT_WRITE: u32 = 2;
+/// %R: the fault happened while fetching an instruction
+pub const FAULT_EXECUTE: u32 = 3;
+/// %R: set if the page is mapped but does not allow the access, clear if it is not mapped
+pub const FAULT_PROTECTION: u32 = 1 << 2;
+
+/// number of cached page table entries per thread
+pub(crate) const TLB_ENTRIES: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Access {
+    Read,
+    Write,
+    Execute,
+}
+
+impl Access {
+    fn pte_bit(self) -> u32 {
+        match self {
+            Access::Read => PTE_READ,
+            Access::Write => PTE_WRITE,
+            Access::Execute => PTE_EXECUTE,
+        }
+    }
+
+    fn fault(self) -> u32 {
+        match self {
+            Access::Read => FAULT_READ,
+            Access::Write => FAULT_WRITE,
+            Access::Execute => FAULT_EXECUTE,
+        }
+    }
+}
+
+impl ThreadCore {
+    /// whether addresses of this thread are translated by the page table at %P
+    #[inline]
+    pub(crate) fn paging(&self) -> bool {
+        self.registers[REG_P as usize] != 0
+    }
+
+    /// physical address of addr, or the FAULT_* reason why it can not be accessed. never raises a page fault
+    #[inline]
+    pub(crate) fn translate(&self, addr: u32, access: Access) -> Result<u32, u32> {
+        if !self.paging() { return Ok(addr); }
+        let pte = self.lookup_page(addr / PAGE_SIZE).ok_or(access.fault())?;
+        if pte & access.pte_bit() == 0 || (pte & PTE_USER == 0 && !self.has_permission(PERM_BIT_SUPERVISOR)) {
+            return Err(access.fault() | FAULT_PROTECTION);
+        }
+        Ok(pte & PTE_FRAME | addr & !PTE_FRAME)
+    }
+
+    /// page table entry of a virtual page number, from the tlb or by walking the page table.
+    /// None if the page is not mapped or its frame is not entirely in memory
+    fn lookup_page(&self, page: u32) -> Option<u32> {
+        let slot = page as usize % TLB_ENTRIES;
+        let (tag, pte) = self.tlb[slot];
+        if tag == page + 1 { return Some(pte); }
+        let dir = self.registers[REG_P as usize] & PTE_FRAME;
+        let pde = self.ram_u32(dir + (page >> 10) * 4)?;
+        if pde & PTE_PRESENT == 0 { return None; }
+        let pte = self.ram_u32((pde & PTE_FRAME) + (page & 0x3FF) * 4)?;
+        if pte & PTE_PRESENT == 0 || (pte & PTE_FRAME) as usize + PAGE_SIZE as usize > self.machine.memory.len() { return None; }
+        unsafe { self.mutator().tlb[slot] = (page + 1, pte); }
+        Some(pte)
+    }
+
+    /// a word of ram. page tables are always read from ram, never from memory mapped devices
+    fn ram_u32(&self, addr: u32) -> Option<u32> {
+        let addr = addr as usize;
+        self.machine.memory.get(addr..addr + 4).map(|word| u32::from_le_bytes(word.try_into().unwrap()))
+    }
+
+    /// physical start of [addr, addr + len) if all of its pages allow the access and are contiguous in memory. never raises a page fault
+    pub(crate) fn translate_range(&self, addr: u32, len: u32, access: Access) -> Option<u32> {
+        if !self.paging() { return Some(addr); }
+        let start = self.translate(addr, access).ok()?;
+        let last = addr.checked_add(len.max(1) - 1)? & PTE_FRAME;
+        let mut page = addr & PTE_FRAME;
+        while page < last {
+            page += PAGE_SIZE;
+            if self.translate(page, access).ok()? != start + (page - addr) { return None; }
+        }
+        Some(start)
+    }
+
+    /// translates addr for the current instruction. on a page fault the instruction is stopped and restarted,
+    /// if the thread can take VECTOR_PAGE_FAULT, otherwise FLAG_BIT_E is set. None once the instruction faulted
+    #[inline]
+    pub(crate) fn access(&self, addr: u32, access: Access) -> Option<u32> {
+        if self.fault.is_some() { return None; }
+        match self.translate(addr, access) {
+            Ok(addr) => Some(addr),
+            Err(reason) => unsafe {
+                let mutor = self.mutator();
+                if self.takes_page_faults() {
+                    mutor.fault = Some((addr, reason));
+                } else {
+                    mutor.registers[REG_A as usize] = addr;
+                    mutor.registers[REG_R as usize] = reason;
+                    self.set_error();
+                }
+                None
+            }
+        }
+    }
+
+    /// page faults restart the instruction only if the handler is going to run, otherwise it would fault forever
+    fn takes_page_faults(&self) -> bool {
+        self.instr_paging && self.registers[REG_F as usize] & FLAG_BIT_I != 0 && self.registers[REG_M as usize] & (1 << VECTOR_PAGE_FAULT) != 0
+    }
+
+    /// checks that the words at addrs can be written before the current instruction has any effect.
+    /// false if that raised a page fault and the instruction has to stop
+    pub(crate) fn writable(&self, addrs: &[u32]) -> bool {
+        if self.paging() {
+            for &addr in addrs {
+                for addr in [addr, addr.wrapping_add(3)] {
+                    self.access(addr, Access::Write);
+                }
+            }
+        }
+        self.fault.is_none()
+    }
+
+    /// checks that the stack slots the results in regs are pushed to can be written, see `writable`
+    pub(crate) fn results_writable(&self, regs: &[Option<u8>]) -> bool {
+        if !self.paging() { return self.fault.is_none(); }
+        let mut sp = self.registers[REG_S as usize];
+        // results are pushed in order, each onto the next slot
+        regs.iter().flatten().filter(|reg| **reg == 0b0111_1110).all(|_| {
+            sp = sp.wrapping_add(4);
+            self.writable(&[sp])
+        }) && self.fault.is_none()
+    }
+
+    /// saves the registers before an instruction which may fault
+    #[inline]
+    pub(crate) fn begin_instr(&self) {
+        unsafe {
+            let mutor = self.mutator();
+            mutor.fault = None;
+            mutor.instr_paging = self.paging();
+            if mutor.instr_paging {
+                mutor.instr_registers = self.registers;
+            }
+        }
+    }
+
+    /// undoes the current instruction after a page fault and raises VECTOR_PAGE_FAULT, so it is executed again
+    /// once the handler returns. %A is the faulting address and %R the FAULT_* reason
+    pub(crate) fn restart_faulted(&self) {
+        unsafe {
+            let mutor = self.mutator();
+            let Some((addr, reason)) = mutor.fault.take() else { return };
+            mutor.registers = mutor.instr_registers;
+            mutor.registers[REG_A as usize] = addr;
+            mutor.registers[REG_R as usize] = reason;
+            mutor.blocked = false;
+            // the instruction may have changed %P before it faulted
+            mutor.flush_tlb();
+        }
+        self.raise_interrupt(VECTOR_PAGE_FAULT);
+    }
+
+    /// a handler can not be entered if the vector table entry or the stack of the trap frame is not mapped,
+    /// the thread crashes with a double fault. false if it did
+    pub(crate) fn check_trap_frame(&self, table_entry: Option<u32>) -> bool {
+        if !self.paging() { return true; }
+        let sp = self.registers[REG_S as usize];
+        let words = table_entry.map(|addr| (addr, Access::Read)).into_iter()
+            .chain((1..=3).map(|i| (sp.wrapping_add(i * 4), Access::Write)));
+        for (addr, access) in words {
+            for addr in [addr, addr.wrapping_add(3)] {
+                if let Err(reason) = self.translate(addr, access) {
+                    self.crash(format!("double fault: page fault {reason} at 0x{addr:08X} while entering a handler"));
+                    return false;
+                }
+            }
+        }
+        true
+    }
+
+    /// drops all cached page table entries
+    pub(crate) fn flush_tlb(&self) {
+        unsafe { self.mutator().tlb = [(0, 0); TLB_ENTRIES]; }
+    }
+
+    /// sets %P, sets FLAG_BIT_E without PERM_BIT_SUPERVISOR
+    pub(crate) fn set_page_table(&self, addr: u32) {
+        if !self.has_permission(PERM_BIT_SUPERVISOR) { return self.set_error(); }
+        unsafe { self.mutator().registers[REG_P as usize] = addr; }
+        self.flush_tlb();
+    }
+}
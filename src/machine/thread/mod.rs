@@ -1,11 +1,14 @@
 pub(crate) mod instructions;
 pub(crate) mod instructions_impl;
+pub(crate) mod interrupts;
+pub(crate) mod syscalls;
+pub(crate) mod mmu;
 
-use std::{sync::{Arc, atomic::{Ordering, AtomicU8}}, collections::HashMap};
+use std::{sync::{Arc, Mutex, atomic::{Ordering, AtomicU8, AtomicU32, AtomicBool}}, collections::HashMap, thread::JoinHandle};
 
-use crate::{Machine};
-
-use super::MachineCtx;
+use super::dma::{Transfer, DMA_DESC_SIZE, DMA_DESC_MODE, DMA_DESC_SRC, DMA_DESC_DST, DMA_DESC_LEN, DMA_DESC_STATUS, DMA_DESC_IRQ, DMA_MEM_TO_MEM, DMA_MEM_TO_DEVICE, DMA_DEVICE_TO_MEM, DMA_STATUS_ERROR};
+use self::mmu::{Access, TLB_ENTRIES, PAGE_SIZE};
+use super::{MachineCtx, ExitStatus, ExitReason, VmError, Scheduler, error::panic_message, device::{Device, MmioRegion, DEVICE_CONSOLE}};
 
 /// Instruction Pointer
 pub const REG_I: u32 = 0x30;
@@ -17,9 +20,26 @@ pub const REG_S: u32 = 0x32;
 pub const REG_F: u32 = 0x33;
 /// carry/overflow/underflow/shift in/out
 pub const REG_C: u32 = 0x34;
+/// Signal Handler address, 0 if signals are not handled asynchronously
+pub const REG_H: u32 = 0x35;
+/// interrupt Vector table address
+pub const REG_V: u32 = 0x36;
+/// interrupt Mask, bit n enables vector n
+pub const REG_M: u32 = 0x37;
+/// error Number of the last syscall, 0 on success
+pub const REG_N: u32 = 0x38;
+/// Page table address, 0 if paging is disabled. only writable with PERM_BIT_SUPERVISOR
+pub const REG_P: u32 = 0x39;
+/// fault Address of the last page fault
+pub const REG_A: u32 = 0x3A;
+/// fault Reason of the last page fault, see FAULT_*
+pub const REG_R: u32 = 0x3B;
 
 // last reg + 1
-pub const NUM_REGS: u32 = 0x35;
+pub const NUM_REGS: u32 = 0x3C;
+
+/// number of interrupt vectors
+pub const NUM_INTERRUPTS: u32 = 32;
 
 // Flags
 /// zero: Z = a == b
@@ -39,11 +59,52 @@ pub const FLAG_PLACE_E: u32 = 3;
 /// Error flag: permission/access/out of bounds/invalid arg
 pub const FLAG_BIT_E: u32 = 1 << FLAG_PLACE_E;
 /// floating point: -inf
+#[allow(dead_code)]
 pub const FLAG_PLACE_M: u32 = 4;
 /// integer division by zero
 pub const FLAG_PLACE_L: u32 = 5;
 /// integer division by zero
 pub const FLAG_BIT_L: u32 = 1 << FLAG_PLACE_L;
+/// interrupts enabled
+pub const FLAG_PLACE_I: u32 = 6;
+/// interrupts enabled
+pub const FLAG_BIT_I: u32 = 1 << FLAG_PLACE_I;
+
+// Permissions
+/// spawn: may create and start child threads
+pub const PERM_PLACE_SPAWN: u32 = 0;
+/// spawn: may create and start child threads
+pub const PERM_BIT_SPAWN: u32 = 1 << PERM_PLACE_SPAWN;
+/// device: may access devices
+pub const PERM_PLACE_DEVICE: u32 = 1;
+/// device: may access devices
+pub const PERM_BIT_DEVICE: u32 = 1 << PERM_PLACE_DEVICE;
+/// memory range: may change the access range of descendants
+pub const PERM_PLACE_MEM_RANGE: u32 = 2;
+/// memory range: may change the access range of descendants
+pub const PERM_BIT_MEM_RANGE: u32 = 1 << PERM_PLACE_MEM_RANGE;
+/// atomics: may use atomic memory operations
+pub const PERM_PLACE_ATOMIC: u32 = 3;
+/// atomics: may use atomic memory operations
+pub const PERM_BIT_ATOMIC: u32 = 1 << PERM_PLACE_ATOMIC;
+/// host fs: may use the host filesystem syscalls
+pub const PERM_PLACE_HOST_FS: u32 = 4;
+/// host fs: may use the host filesystem syscalls
+pub const PERM_BIT_HOST_FS: u32 = 1 << PERM_PLACE_HOST_FS;
+/// supervisor: may change the page table and access pages without PTE_USER
+pub const PERM_PLACE_SUPERVISOR: u32 = 5;
+/// supervisor: may change the page table and access pages without PTE_USER
+pub const PERM_BIT_SUPERVISOR: u32 = 1 << PERM_PLACE_SUPERVISOR;
+
+// Permission registers
+/// permission bits, see PERM_BIT_*
+pub const PR_PERMISSIONS: u32 = 0;
+/// lowest accessible address
+pub const PR_ACCESS_MIN: u32 = 1;
+/// highest accessible address + 1
+pub const PR_ACCESS_MAX: u32 = 2;
+/// %P, only writable with PERM_BIT_SUPERVISOR while the thread is ready
+pub const PR_PAGE_TABLE: u32 = 3;
 
 
 pub struct ThreadCore {
@@ -59,27 +120,74 @@ pub struct ThreadCore {
     thread_id: u32,
     parent_thread_id: u32,
 
-    registers: [u32;64]
+    exit_code: AtomicU32,
+    halted: AtomicBool,
+    /// why the thread crashed, see `crash`
+    crash: Mutex<Option<String>>,
+
+    /// t_sig: pending signal bits, set by ancestors
+    signal: AtomicU32,
+    in_signal_handler: bool,
+    /// %S right after the trap frame of the running signal handler was pushed
+    signal_stack: u32,
+    /// pending interrupt vectors
+    interrupts: AtomicU32,
+    /// the current instruction is `wfi` waiting for an interrupt
+    waiting_for_interrupt: bool,
+    /// address of the currently executed instruction
+    instr_addr: u32,
+    /// stack pointer before the currently executed instruction
+    instr_stack: u32,
+    /// the current instruction is waiting and will be executed again
+    blocked: bool,
+    /// registers before the currently executed instruction, only saved while paging is enabled
+    instr_registers: [u32;64],
+    /// paging was enabled when the current instruction started, so a page fault can undo it
+    instr_paging: bool,
+    /// page fault of the current instruction: the address and the FAULT_* reason
+    fault: Option<(u32, u32)>,
+    /// cached page table entries, indexed by the low bits of the page number. the tag is the page number + 1, 0 if empty
+    tlb: [(u32, u32); TLB_ENTRIES],
+
+    pub(crate) registers: [u32;64]
 }
 
 impl ThreadCore {
-    pub(crate) fn launch_main(machine: &mut Machine) -> u32 {
-        let id = machine.ctx.next_thead_id.fetch_add(1, Ordering::SeqCst);
-        if id != 0 { panic!("tried to create main thread with id {id}."); }
-        let main = Arc::new(ThreadCore {
-            machine: machine.ctx.clone(),
+    fn new(machine: &Arc<MachineCtx>, thread_id: u32, parent_thread_id: u32, access_min_addr: u32, access_max_addr: u32, permissions: u32, registers: [u32;64]) -> Self {
+        ThreadCore {
+            machine: machine.clone(),
             children: Default::default(),
             state: AtomicU8::new(0),
-            access_min_addr: 0,
-            access_max_addr: machine.ctx.memory.len() as u32,
-            permissions: !0,
-            thread_id: id,
-            parent_thread_id: 0,
-            registers: [0u32;64],
-        });
-        unsafe { machine.ctx.mutator().threads.insert(id, main.clone()); }
-        main.start();
-        id
+            access_min_addr,
+            access_max_addr,
+            permissions,
+            thread_id,
+            parent_thread_id,
+            exit_code: AtomicU32::new(0),
+            halted: AtomicBool::new(false),
+            crash: Mutex::new(None),
+            signal: AtomicU32::new(0),
+            in_signal_handler: false,
+            signal_stack: 0,
+            interrupts: AtomicU32::new(0),
+            waiting_for_interrupt: false,
+            instr_addr: 0,
+            instr_stack: 0,
+            blocked: false,
+            instr_registers: [0u32;64],
+            instr_paging: false,
+            fault: None,
+            tlb: [(0, 0); TLB_ENTRIES],
+            registers,
+        }
+    }
+
+    pub(crate) fn create_main(machine: &Arc<MachineCtx>) -> Arc<ThreadCore> {
+        let id = machine.next_thead_id.fetch_add(1, Ordering::SeqCst);
+        if id != 0 { panic!("tried to create main thread with id {id}."); }
+        let main = Arc::new(ThreadCore::new(machine, id, 0, 0, machine.memory.len() as u32, !0, [0u32;64]));
+        machine.threads.write().unwrap().insert(id, main.clone());
+        main
     }
 
     /// from a permission standpoint a thread is it's own child and parent
@@ -87,7 +195,7 @@ impl ThreadCore {
         if tid == self.thread_id { return true; }
         let mut id = self.thread_id;
         while id != 0 {
-            match self.machine.threads.get(&id) {
+            match self.machine.thread(id) {
                 Some(t) => id = t.parent_thread_id,
                 None => return false
             }
@@ -100,64 +208,552 @@ impl ThreadCore {
 
     /// from a permission standpoint a thread is it's own parent and child
     fn is_parent_of(&self, tid: u32) -> bool {
-        match self.machine.threads.get(&tid) {
+        match self.machine.thread(tid) {
             Some(t) => t.is_child_of(self.thread_id),
-            None => return false
+            None => false
         }
     }
 
-    fn start(self: Arc<Self>) {
-        std::thread::spawn(move || {
-            self.state.store(1, Ordering::Release);
-            self.run();
-            self.state.store(3, Ordering::Release);
+    /// creates a ready child thread which inherits the access range and permissions of this thread
+    fn create_child(&self, registers: [u32;64]) -> u32 {
+        let id = self.machine.next_thead_id.fetch_add(1, Ordering::SeqCst);
+        let child = Arc::new(ThreadCore::new(&self.machine, id, self.thread_id, self.access_min_addr, self.access_max_addr, self.permissions, registers));
+        self.machine.threads.write().unwrap().insert(id, child.clone());
+        unsafe { self.mutator().children.insert(id, child); }
+        id
+    }
+
+    /// creates a ready child thread starting at entry with its own stack, in the same address space
+    pub(crate) fn spawn(&self, entry: u32, stack: u32) -> Option<u32> {
+        if !self.has_permission(PERM_BIT_SPAWN) { return None; }
+        let mut registers = [0u32;64];
+        registers[REG_P as usize] = self.registers[REG_P as usize];
+        registers[REG_I as usize] = entry;
+        registers[REG_B as usize] = stack;
+        registers[REG_S as usize] = stack;
+        Some(self.create_child(registers))
+    }
+
+    /// creates a ready child thread with a copy of all registers except for the stack.
+    /// in the child the register `out` is set to 0. None if `out` is no register the child can see this 0 in:
+    /// the stack, a literal, or %I, %B and %S which the child starts with its own values in
+    pub(crate) fn fork(&self, stack: u32, out: u8) -> Option<u32> {
+        if !self.has_permission(PERM_BIT_SPAWN) { return None; }
+        let out = out as u32;
+        if out >= NUM_REGS || [REG_I, REG_B, REG_S].contains(&out) { return None; }
+        let mut registers = self.registers;
+        registers[REG_B as usize] = stack;
+        registers[REG_S as usize] = stack;
+        registers[out as usize] = 0;
+        Some(self.create_child(registers))
+    }
+
+    /// starts a ready descendant thread, sets FLAG_BIT_E if that is not possible
+    pub(crate) fn start_child(&self, tid: u32) {
+        match self.descendant(tid) {
+            Some(child) if child.is_ready() && self.has_permission(PERM_BIT_SPAWN) => { child.start(); },
+            _ => self.set_error()
+        }
+    }
+
+    /// narrows the access range of a ready descendant thread to [min, max),
+    /// sets FLAG_BIT_E if the range is not within the range of this thread
+    pub(crate) fn narrow_child(&self, tid: u32, min: u32, max: u32) {
+        match self.descendant(tid) {
+            Some(child) if child.is_ready() && self.may_grant_range(min, max) => unsafe {
+                let child = child.mutator();
+                child.access_min_addr = min;
+                child.access_max_addr = max;
+            },
+            _ => self.set_error()
+        }
+    }
+
+    /// sets a permission register of a ready descendant or this thread. a thread can never grant more than it has itself,
+    /// so it can only narrow its own permission registers. sets FLAG_BIT_E on failure
+    pub(crate) fn set_child_pr(&self, tid: u32, pr: u32, value: u32) {
+        let Some(child) = self.descendant(tid) else { return self.set_error() };
+        // a running child may be executing on another os thread right now
+        if !child.is_ready() && child.thread_id != self.thread_id { return self.set_error(); }
+        unsafe {
+            let child_mut = child.mutator();
+            match pr {
+                PR_PERMISSIONS if value & !self.permissions == 0 => child_mut.permissions = value,
+                PR_ACCESS_MIN if self.may_grant_range(value, child.access_max_addr) => child_mut.access_min_addr = value,
+                PR_ACCESS_MAX if self.may_grant_range(child.access_min_addr, value) => child_mut.access_max_addr = value,
+                PR_PAGE_TABLE if self.has_permission(PERM_BIT_SUPERVISOR) && child.is_ready() => {
+                    child_mut.registers[REG_P as usize] = value;
+                    child.flush_tlb();
+                },
+                _ => self.set_error()
+            }
+        }
+    }
+
+    /// reads a permission register of a descendant, None if it is not a descendant or pr is invalid
+    pub(crate) fn get_child_pr(&self, tid: u32, pr: u32) -> Option<u32> {
+        let child = self.descendant(tid)?;
+        match pr {
+            PR_PERMISSIONS => Some(child.permissions),
+            PR_ACCESS_MIN => Some(child.access_min_addr),
+            PR_ACCESS_MAX => Some(child.access_max_addr),
+            PR_PAGE_TABLE => Some(child.registers[REG_P as usize]),
+            _ => None
+        }
+    }
+
+    /// the thread with id tid if this thread is its parent from a permission standpoint
+    fn descendant(&self, tid: u32) -> Option<Arc<ThreadCore>> {
+        if !self.is_parent_of(tid) { return None; }
+        self.machine.thread(tid)
+    }
+
+    /// state of a descendant thread: 0 ready, 1 running, 2 terminating, 3 terminated
+    pub(crate) fn child_state(&self, tid: u32) -> Option<u32> {
+        self.descendant(tid).map(|child| child.state.load(Ordering::Acquire) as u32)
+    }
+
+    /// requests termination of a descendant thread. a ready thread is terminated immediately
+    pub(crate) fn kill_child(&self, tid: u32) {
+        match self.descendant(tid) {
+            Some(child) => if child.state.compare_exchange(0, 3, Ordering::SeqCst, Ordering::SeqCst).is_err() {
+                let _ = child.state.compare_exchange(1, 2, Ordering::SeqCst, Ordering::SeqCst);
+            },
+            None => self.set_error()
+        }
+    }
+
+    /// waits until a descendant thread is terminated and writes its exit code to dest.
+    /// sets FLAG_BIT_E if it is not a descendant or this thread
+    pub(crate) fn join_child(&self, tid: u32, dest: u8) {
+        match self.descendant(tid) {
+            Some(child) if tid != self.thread_id => if child.is_stopped() {
+                self.write_arg(dest, child.exit_code.load(Ordering::Acquire));
+                self.reap(&child);
+            } else {
+                self.block();
+            },
+            _ => self.set_error()
+        }
+    }
+
+    /// forgets a joined child, so guests which keep creating threads do not keep all of them around.
+    /// only its parent reaps it and only once it has no children left, so the ancestry of other threads stays intact
+    fn reap(&self, child: &ThreadCore) {
+        if child.parent_thread_id != self.thread_id || !child.children.is_empty() { return; }
+        self.machine.threads.write().unwrap().remove(&child.thread_id);
+        unsafe { self.mutator().children.remove(&child.thread_id); }
+    }
+
+    /// sets signal bits of a descendant, sets FLAG_BIT_E if it is not a descendant
+    pub(crate) fn send_signal(&self, tid: u32, bits: u32) {
+        match self.descendant(tid) {
+            Some(child) => { child.signal.fetch_or(bits, Ordering::AcqRel); },
+            None => self.set_error()
+        }
+    }
+
+    /// takes all pending signal bits
+    pub(crate) fn poll_signal(&self) -> u32 {
+        self.signal.swap(0, Ordering::AcqRel)
+    }
+
+    /// takes all pending signal bits, waits until there are any
+    pub(crate) fn wait_signal(&self) -> Option<u32> {
+        match self.poll_signal() {
+            0 => { self.block(); None },
+            bits => Some(bits)
+        }
+    }
+
+    /// calls the signal handler if there are pending signals, a handler is set and it is not already running.
+    /// I, F and C are saved on the stack and restored by `sig_ret`
+    #[inline]
+    pub(crate) fn deliver_signal(&self) {
+        let handler = self.registers[REG_H as usize];
+        if handler == 0 || self.in_signal_handler || self.signal.load(Ordering::Relaxed) == 0 { return; }
+        if !self.check_trap_frame(None) { return; }
+        unsafe {
+            let mutor = self.mutator();
+            mutor.push_trap_frame();
+            mutor.in_signal_handler = true;
+            mutor.signal_stack = mutor.registers[REG_S as usize];
+            mutor.registers[REG_I as usize] = handler;
+        }
+    }
+
+    /// whether the trap frame on top of the stack is the one of the signal handler, which only `sig_ret` may pop
+    pub(crate) fn at_signal_frame(&self) -> bool {
+        self.in_signal_handler && self.registers[REG_S as usize] == self.signal_stack
+    }
+
+    /// returns from the signal handler. sets FLAG_BIT_E instead if no signal handler is running
+    pub(crate) fn return_from_signal(&self) {
+        if !self.in_signal_handler { return self.set_error(); }
+        unsafe {
+            let mutor = self.mutator();
+            mutor.pop_trap_frame();
+            mutor.in_signal_handler = false;
+        }
+    }
+
+    /// pushes I, F and C onto the stack
+    fn push_trap_frame(&self) {
+        unsafe {
+            let mutor = self.mutator();
+            for reg in [REG_I, REG_F, REG_C] {
+                mutor.registers[REG_S as usize] += 4;
+                mutor.write_u32(mutor.registers[REG_S as usize], mutor.registers[reg as usize]);
+            }
+        }
+    }
+
+    /// pops C, F and I from the stack
+    fn pop_trap_frame(&self) {
+        unsafe {
+            let mutor = self.mutator();
+            for reg in [REG_C, REG_F, REG_I] {
+                mutor.registers[reg as usize] = mutor.read_u32(mutor.registers[REG_S as usize]);
+                mutor.registers[REG_S as usize] -= 4;
+            }
+        }
+    }
+
+    /// lets the current instruction wait: it is executed again, with the stack as before, once the thread gets its next turn
+    pub(crate) fn block(&self) {
+        unsafe {
+            let mutor = self.mutator();
+            mutor.registers[REG_I as usize] = self.instr_addr;
+            mutor.registers[REG_S as usize] = self.instr_stack;
+            mutor.blocked = true;
+        }
+    }
+
+    /// runs f on a device. None without PERM_BIT_DEVICE or if no device with this id exists
+    pub(crate) fn device<R>(&self, id: u32, f: impl FnOnce(&mut dyn Device) -> R) -> Option<R> {
+        if !self.has_permission(PERM_BIT_DEVICE) { return None; }
+        self.machine.with_device(id, f)
+    }
+
+    /// reads a byte or a word from a device, see `MachineCtx::read_device`. None without PERM_BIT_DEVICE or if no device with this id exists
+    pub(crate) fn read_device(&self, id: u32, word: bool) -> Option<u32> {
+        if !self.has_permission(PERM_BIT_DEVICE) { return None; }
+        self.machine.read_device(id, word)
+    }
+
+    /// flushes a device, sets FLAG_BIT_E if it failed, without PERM_BIT_DEVICE or if no device with this id exists
+    pub(crate) fn flush_device(&self, id: u32) {
+        if !matches!(self.device(id, |device| device.flush()), Some(Ok(()))) { self.set_error() }
+    }
+
+    /// writes a char to the console as utf-8, sets FLAG_BIT_E on an invalid char
+    pub(crate) fn write_console_char(&self, c: u32) {
+        let c = char::from_u32(c).unwrap_or_else(|| { self.set_error(); '�' });
+        let mut buf = [0u8;4];
+        let written = self.device(DEVICE_CONSOLE, |console| for b in c.encode_utf8(&mut buf).bytes() {
+            console.write8(b);
         });
+        if written.is_none() { self.set_error() }
+    }
+
+    #[inline]
+    pub(crate) fn has_permission(&self, perm: u32) -> bool {
+        self.permissions & perm == perm
+    }
+
+    /// whether [min, max) is a valid access range this thread can hand out
+    fn may_grant_range(&self, min: u32, max: u32) -> bool {
+        self.has_permission(PERM_BIT_MEM_RANGE) && min <= max && min >= self.access_min_addr && max <= self.access_max_addr
+    }
+
+    /// starts executing a ready thread. with the threaded scheduler it gets its own os thread
+    pub(crate) fn start(self: Arc<Self>) -> Option<JoinHandle<()>> {
+        if self.state.compare_exchange(0, 1, Ordering::SeqCst, Ordering::SeqCst).is_err() { return None; }
+        if self.machine.scheduler != Scheduler::Threaded { return None; }
+        self.machine.thread_count.fetch_add(1, Ordering::SeqCst);
+        Some(std::thread::spawn(move || {
+            let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| self.run()));
+            if let Err(panic) = &result {
+                self.crash(panic_message(panic.as_ref()));
+            }
+            self.state.store(3, Ordering::Release);
+            self.machine.thread_count.fetch_sub(1, Ordering::SeqCst);
+            if let Err(panic) = result {
+                std::panic::resume_unwind(panic)
+            }
+        }))
     }
     fn run(&self) {
-        loop {
-            // request quit?
-            if self.state.load(Ordering::Relaxed) == 2 { return; }
+        while !self.should_stop() {
+            self.exec_instr();
+            self.machine.tick();
+            if self.blocked {
+                std::thread::yield_now();
+            }
+        }
+    }
+    /// executes up to n instructions on the calling os thread, returns the number of executed instructions.
+    /// stops early if the thread stopped or an instruction is waiting
+    pub(crate) fn step(&self, n: u32) -> u32 {
+        let mut executed = 0;
+        while executed < n && !self.should_stop() {
             self.exec_instr();
+            self.machine.tick();
+            if self.blocked { break; }
+            executed += 1;
+        }
+        if self.should_stop() {
+            self.terminate();
+        }
+        executed
+    }
+    #[inline]
+    fn should_stop(&self) -> bool {
+        // request quit?
+        self.state.load(Ordering::Relaxed) >= 2 || !self.machine.running.load(Ordering::Relaxed)
+    }
+    /// marks a thread which is not executed by an os thread as terminated
+    pub(crate) fn terminate(&self) {
+        self.state.store(3, Ordering::Release);
+    }
+    pub(crate) fn is_ready(&self) -> bool {
+        self.state.load(Ordering::Acquire) == 0
+    }
+    pub(crate) fn is_running(&self) -> bool {
+        self.state.load(Ordering::Acquire) == 1
+    }
+    pub(crate) fn is_stopped(&self) -> bool {
+        self.state.load(Ordering::Acquire) == 3
+    }
+
+    /// stops this thread with the given exit code. halting the main thread stops the whole machine
+    pub(crate) fn halt(&self, code: u32) {
+        self.exit_code.store(code, Ordering::Release);
+        self.halted.store(true, Ordering::Release);
+        self.state.store(2, Ordering::Release);
+        if self.thread_id == 0 {
+            self.machine.running.store(false, Ordering::Release);
+        }
+    }
+
+    /// stops this thread because of a fault the guest can not handle, like a double fault.
+    /// crashing the main thread stops the whole machine
+    pub(crate) fn crash(&self, message: String) {
+        self.crash.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).get_or_insert(message);
+        // a thread which already terminated stays terminated
+        self.state.fetch_max(2, Ordering::AcqRel);
+        if self.thread_id == 0 {
+            self.machine.running.store(false, Ordering::Release);
         }
     }
 
+    /// the fault which crashed this thread, None if it did not crash
+    pub(crate) fn guest_fault(&self) -> Option<VmError> {
+        let message = self.crash.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).clone()?;
+        Some(VmError::GuestFault { thread_id: self.thread_id, ip: self.registers[REG_I as usize], message })
+    }
+
+    pub(crate) fn exit_status(&self) -> ExitStatus {
+        let crashed = self.crash.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).is_some();
+        ExitStatus {
+            code: self.exit_code.load(Ordering::Acquire),
+            reason: if crashed { ExitReason::Faulted } else if self.halted.load(Ordering::Acquire) { ExitReason::Halted } else { ExitReason::Terminated },
+            registers: self.registers,
+        }
+    }
+
+    /// records a panic of this thread as the fault which crashed it and returns it
+    pub(crate) fn fault(&self, panic: Box<dyn std::any::Any + Send>) -> VmError {
+        self.crash(panic_message(panic.as_ref()));
+        self.guest_fault().expect("the crash was just recorded")
+    }
+
     #[inline]
-    pub(crate)fn atomic_op<F: FnOnce(u32) -> u32>(&self, addr: u32, f: F) {
-        while self.machine.atomic_lock.swap(true, Ordering::SeqCst) { std::thread::yield_now() }
-        f(0);
-        self.machine.atomic_lock.store(false, Ordering::SeqCst);
+    pub(crate) fn set_error(&self) {
+        unsafe { self.mutator().registers[REG_F as usize] |= FLAG_BIT_E; }
     }
+    /// replaces the word at addr with f(old) while holding the machine wide atomic lock, returns the old value.
+    /// None without PERM_BIT_ATOMIC or if addr is not accessible
     #[inline]
-    pub(crate)fn read_u8(&self, addr: u32) -> u8 {
+    pub(crate)fn atomic_op<F: FnOnce(u32) -> u32>(&self, addr: u32, f: F) -> Option<u32> {
+        if !self.has_permission(PERM_BIT_ATOMIC) || addr < self.access_min_addr || addr.checked_add(3).is_none_or(|end| end >= self.access_max_addr) {
+            return None;
+        }
+        // the word is written after reading it, so it has to be writable before anything happens
+        if !self.writable(&[addr]) { return None; }
+        while self.machine.atomic_lock.swap(true, Ordering::Acquire) { std::thread::yield_now() }
+        let old = self.read_u32(addr);
+        self.write_u32(addr, f(old));
+        self.machine.atomic_lock.store(false, Ordering::Release);
+        Some(old)
+    }
+    /// stores new at addr if it contains expected. sets FLAG_BIT_Z on success, FLAG_BIT_E on error
+    pub(crate) fn atomic_cas(&self, addr: u32, expected: u32, new: u32) {
+        match self.atomic_op(addr, |old| if old == expected { new } else { old }) {
+            Some(old) => unsafe {
+                let mutor = self.mutator();
+                if old == expected { mutor.registers[REG_F as usize] |= FLAG_BIT_Z; }
+                else { mutor.registers[REG_F as usize] &= !FLAG_BIT_Z; }
+            },
+            None => self.set_error()
+        }
+    }
+    /// physical start of [addr, addr + len) if it is ram this thread can access, without any memory mapped device in it
+    pub(crate) fn ram_range(&self, addr: u32, len: u32, access: Access) -> Option<u32> {
+        let end = addr.checked_add(len)?;
+        if addr < self.access_min_addr || end > self.access_max_addr { return None; }
+        let start = self.translate_range(addr, len, access)?;
+        let end = start.checked_add(len)?;
+        (end as usize <= self.machine.memory.len() && !self.machine.mmio.iter().any(|region| region.overlaps(start, end))).then_some(start)
+    }
+    /// queues the transfer of the descriptor at desc. sets FLAG_BIT_E and the status to `DMA_STATUS_ERROR` if it is invalid,
+    /// not accessible or the queue is full. the engine works on the physical addresses the ranges had at this point
+    pub(crate) fn start_dma(&self, desc: u32) {
+        let Some(desc) = self.ram_range(desc, DMA_DESC_SIZE, Access::Write) else { return self.set_error() };
+        let word = |offset: u32| {
+            let addr = (desc + offset) as usize;
+            u32::from_le_bytes(self.machine.memory[addr..addr + 4].try_into().unwrap())
+        };
+        let (mode, src, dst, len) = (word(DMA_DESC_MODE), word(DMA_DESC_SRC), word(DMA_DESC_DST), word(DMA_DESC_LEN));
+        let ranges = match mode {
+            DMA_MEM_TO_MEM => self.ram_range(src, len, Access::Read).zip(self.ram_range(dst, len, Access::Write)),
+            DMA_MEM_TO_DEVICE if self.has_permission(PERM_BIT_DEVICE) && self.machine.devices.contains_key(&dst) => {
+                self.ram_range(src, len, Access::Read).map(|src| (src, dst))
+            },
+            DMA_DEVICE_TO_MEM if self.has_permission(PERM_BIT_DEVICE) && self.machine.devices.contains_key(&src) => {
+                self.ram_range(dst, len, Access::Write).map(|dst| (src, dst))
+            },
+            _ => None
+        };
+        let queued = ranges.is_some_and(|(src, dst)| self.machine.submit_dma(Transfer { thread_id: self.thread_id, desc, mode, src, dst, len, irq: word(DMA_DESC_IRQ), done: 0 }));
+        if !queued {
+            let _ = self.machine.write_memory(desc + DMA_DESC_STATUS, &DMA_STATUS_ERROR.to_le_bytes());
+            self.set_error();
+        }
+    }
+    /// the memory mapped region containing the physical address addr
+    #[inline]
+    fn mmio_region(&self, addr: u32) -> Option<MmioRegion> {
+        if self.machine.mmio.is_empty() { return None; }
+        self.machine.mmio.iter().find(|region| region.contains(addr)).copied()
+    }
+    /// runs f on the device of a memory mapped region. sets FLAG_BIT_E without PERM_BIT_DEVICE or if the device is missing
+    fn mmio_device<R: Default>(&self, region: MmioRegion, f: impl FnOnce(&mut dyn Device) -> R) -> R {
+        self.device(region.device, f).unwrap_or_else(|| {
+            self.set_error();
+            R::default()
+        })
+    }
+    #[inline]
+    fn load8_phys(&self, addr: u32) -> u8 {
+        match self.mmio_region(addr) {
+            Some(region) => self.mmio_device(region, |dev| dev.load8(addr - region.start)),
+            None => self.machine.memory[addr as usize]
+        }
+    }
+    #[inline]
+    fn store8_phys(&self, addr: u32, value: u8) {
+        match self.mmio_region(addr) {
+            Some(region) => self.mmio_device(region, |dev| dev.store8(addr - region.start, value)),
+            None => unsafe { self.machine.mem_mut()[addr as usize] = value; }
+        }
+    }
+    #[inline]
+    fn load32_phys(&self, addr: u32) -> u32 {
+        if !self.machine.mmio.is_empty() && self.machine.mmio.iter().any(|region| region.overlaps(addr, addr + 4)) {
+            return match self.mmio_region(addr) {
+                Some(region) if addr + 4 <= region.end => self.mmio_device(region, |dev| dev.load(addr - region.start)),
+                // partially mapped
+                _ => u32::from_le_bytes([self.load8_phys(addr), self.load8_phys(addr + 1), self.load8_phys(addr + 2), self.load8_phys(addr + 3)])
+            };
+        }
+        u32::from_le_bytes(self.machine.memory[addr as usize .. addr as usize + 4].try_into().unwrap())
+    }
+    #[inline]
+    fn store32_phys(&self, addr: u32, value: u32) {
+        if !self.machine.mmio.is_empty() && self.machine.mmio.iter().any(|region| region.overlaps(addr, addr + 4)) {
+            match self.mmio_region(addr) {
+                Some(region) if addr + 4 <= region.end => self.mmio_device(region, |dev| dev.store(addr - region.start, value)),
+                // partially mapped
+                _ => for (i, b) in value.to_le_bytes().into_iter().enumerate() {
+                    self.store8_phys(addr + i as u32, b);
+                }
+            }
+            return;
+        }
+        let value = value.to_le_bytes();
+        unsafe { std::ptr::copy_nonoverlapping(&value as *const [u8;4] as *mut _, (self.machine.mem_mut().as_ptr() as usize + addr as usize) as *mut u8, std::mem::size_of::<u32>()); }
+    }
+    /// whether the word at addr crosses a page boundary, so its bytes have to be translated one by one
+    #[inline]
+    fn splits_page(&self, addr: u32) -> bool {
+        self.paging() && addr % PAGE_SIZE > PAGE_SIZE - 4
+    }
+    #[inline]
+    fn load_u8(&self, addr: u32, access: Access) -> u8 {
         if addr >= self.access_min_addr && addr < self.access_max_addr {
-            self.machine.memory[addr as usize]
+            self.access(addr, access).map_or(0, |addr| self.load8_phys(addr))
         } else {
             unsafe { self.mutator().registers[REG_F as usize] |= FLAG_BIT_E; }
             0
         }
     }
     #[inline]
-    pub(crate)fn write_u8(&self, addr: u32, value: u8) {
-        if addr >= self.access_min_addr && addr < self.access_max_addr {
-            unsafe { self.machine.mem_mut()[addr as usize] = value; }
+    fn load_u32(&self, addr: u32, access: Access) -> u32 {
+        if addr >= self.access_min_addr && addr + 3 < self.access_max_addr {
+            if self.splits_page(addr) {
+                return u32::from_le_bytes([0, 1, 2, 3].map(|i| self.load_u8(addr + i, access)));
+            }
+            self.access(addr, access).map_or(0, |addr| self.load32_phys(addr))
         } else {
             unsafe { self.mutator().registers[REG_F as usize] |= FLAG_BIT_E; }
+            0
         }
     }
     #[inline]
-    pub(crate)fn read_u32(&self, addr: u32) -> u32 {
-        if addr >= self.access_min_addr && addr + 3 < self.access_max_addr {
-            u32::from_le_bytes(self.machine.memory[addr as usize .. addr as usize + 4].try_into().unwrap())
+    pub(crate)fn read_u8(&self, addr: u32) -> u8 {
+        self.load_u8(addr, Access::Read)
+    }
+    #[inline]
+    pub(crate)fn write_u8(&self, addr: u32, value: u8) {
+        if addr >= self.access_min_addr && addr < self.access_max_addr {
+            if let Some(addr) = self.access(addr, Access::Write) {
+                self.store8_phys(addr, value);
+            }
         } else {
             unsafe { self.mutator().registers[REG_F as usize] |= FLAG_BIT_E; }
-            0
         }
     }
     #[inline]
+    pub(crate)fn read_u32(&self, addr: u32) -> u32 {
+        self.load_u32(addr, Access::Read)
+    }
+    /// reads a word like `read_u32`, but never sets FLAG_BIT_E or raises a page fault. None if it is not accessible
+    pub(crate) fn peek_u32(&self, addr: u32) -> Option<u32> {
+        if addr < self.access_min_addr || addr.checked_add(3)? >= self.access_max_addr { return None; }
+        self.translate_range(addr, 4, Access::Read).map(|addr| self.load32_phys(addr))
+    }
+    /// reads a word of the instruction stream, which needs PTE_EXECUTE instead of PTE_READ
+    #[inline]
+    pub(crate)fn fetch_u32(&self, addr: u32) -> u32 {
+        self.load_u32(addr, Access::Execute)
+    }
+    #[inline]
     pub(crate)fn write_u32(&self, addr: u32, value: u32) {
         if addr >= self.access_min_addr && addr + 3 < self.access_max_addr {
-            let value = value.to_le_bytes();
-            unsafe { std::ptr::copy_nonoverlapping(&value as *const [u8;4] as *mut _, (self.machine.mem_mut().as_ptr() as usize + addr as usize) as *mut u8, std::mem::size_of::<u32>()); }
+            if self.splits_page(addr) {
+                // all bytes are translated first, so a fault in the second page does not write the first
+                let bytes = [0, 1, 2, 3].map(|i| self.access(addr + i, Access::Write));
+                if let [Some(a), Some(b), Some(c), Some(d)] = bytes {
+                    for (addr, b) in [a, b, c, d].into_iter().zip(value.to_le_bytes()) {
+                        self.store8_phys(addr, b);
+                    }
+                }
+                return;
+            }
+            if let Some(addr) = self.access(addr, Access::Write) {
+                self.store32_phys(addr, value);
+            }
         } else {
             unsafe { self.mutator().registers[REG_F as usize] |= FLAG_BIT_E; }
         }
@@ -167,13 +763,13 @@ impl ThreadCore {
         unsafe { 
             let mutor = self.mutator();
             if reg == 0b0111_1111 {
-                let v = self.read_u32(self.registers[REG_I as usize]);
+                let v = self.fetch_u32(self.registers[REG_I as usize]);
                 self.advance_ip();
-                return v;
+                v
             } else if reg == 0b0111_1110 {
                 let v = self.read_u32(mutor.registers[REG_S as usize]);
                 mutor.registers[REG_S as usize] -= 4;
-                return v;
+                v
             } else if reg < NUM_REGS as u8 {
                 mutor.registers[reg as usize]
             } else {
@@ -189,7 +785,8 @@ impl ThreadCore {
             if reg == 0b0111_1110 {
                 mutor.registers[REG_S as usize] += 4;
                 self.write_u32(mutor.registers[REG_S as usize], val);
-                return;
+            } else if reg == REG_P as u8 {
+                self.set_page_table(val);
             } else if reg < NUM_REGS as u8 {
                 mutor.registers[reg as usize] = val;
             } else {
@@ -210,14 +807,6 @@ impl ThreadCore {
     #[allow(clippy::mut_from_ref)]
     pub unsafe fn mutator(&self) -> &mut Self {
         #[allow(invalid_reference_casting)]
-        &mut *(self as *const _ as *mut _)
+        unsafe { &mut *(self as *const _ as *mut _) }
     }
 }
-
-impl Drop for ThreadCore {
-    fn drop(&mut self) {
-        self.state.store(2, Ordering::Release);
-        while self.state.load(Ordering::Relaxed) != 3 { std::thread::yield_now() }
-        self.machine.thread_count.fetch_sub(1, Ordering::SeqCst);
-    }
-}
\ No newline at end of file
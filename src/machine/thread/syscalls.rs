@@ -0,0 +1,101 @@
+use crate::machine::host_fs::{HostFs, SysError};
+
+use super::{ThreadCore, REG_N, PERM_BIT_HOST_FS, mmu::{Access, PAGE_SIZE}};
+
+/// open(%0 path, %1 flags OPEN_*) -> %0 fd
+pub const SYS_OPEN: u32 = 1;
+/// close(%0 fd)
+pub const SYS_CLOSE: u32 = 2;
+/// read(%0 fd, %1 buf, %2 len) -> %0 number of bytes read, 0 at the end of the file
+pub const SYS_READ: u32 = 3;
+/// write(%0 fd, %1 buf, %2 len) -> %0 number of bytes written
+pub const SYS_WRITE: u32 = 4;
+/// seek(%0 fd, %1 offset as i32, %2 whence: 0 start, 1 current, 2 end) -> %0 new position
+pub const SYS_SEEK: u32 = 5;
+/// stat(%0 path, %1 buf): writes size and kind STAT_* as two words to buf
+pub const SYS_STAT: u32 = 6;
+/// readdir(%0 path, %1 index, %2 buf, %3 len) -> %0 length of the name of the nth entry, written null terminated to buf
+pub const SYS_READDIR: u32 = 7;
+
+/// longest path a guest can pass, including the null terminator
+const MAX_PATH: u32 = 4096;
+
+impl ThreadCore {
+    /// executes syscall n with args in %0..%3, the result is written to %0.
+    /// on failure FLAG_BIT_E is set and %N is the error code, on success %N is 0
+    pub(crate) fn syscall(&self, n: u32) {
+        let args = [self.registers[0], self.registers[1], self.registers[2], self.registers[3]];
+        let result = match &self.machine.host_fs {
+            Some(fs) if self.has_permission(PERM_BIT_HOST_FS) => {
+                let mut fs = fs.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+                self.host_fs_call(&mut fs, n, args)
+            },
+            _ => Err(SysError::NoSys)
+        };
+        unsafe {
+            let mutor = self.mutator();
+            match result {
+                Ok(value) => {
+                    mutor.registers[0] = value;
+                    mutor.registers[REG_N as usize] = 0;
+                },
+                Err(e) => {
+                    mutor.registers[REG_N as usize] = e as u32;
+                    self.set_error();
+                }
+            }
+        }
+    }
+
+    fn host_fs_call(&self, fs: &mut HostFs, n: u32, [a, b, c, d]: [u32;4]) -> Result<u32, SysError> {
+        match n {
+            SYS_OPEN => fs.open(&self.guest_str(a)?, b),
+            SYS_CLOSE => fs.close(a).map(|_| 0),
+            SYS_READ => fs.read(a, self.guest_memory(b, c, Access::Write)?),
+            SYS_WRITE => fs.write(a, self.guest_memory(b, c, Access::Read)?),
+            SYS_SEEK => fs.seek(a, b as i32, c),
+            SYS_STAT => {
+                let (size, kind) = fs.stat(&self.guest_str(a)?)?;
+                let buf = self.guest_memory(b, 8, Access::Write)?;
+                buf[0..4].copy_from_slice(&size.to_le_bytes());
+                buf[4..8].copy_from_slice(&kind.to_le_bytes());
+                Ok(0)
+            },
+            SYS_READDIR => {
+                let name = fs.read_dir(&self.guest_str(a)?, b)?;
+                let buf = self.guest_memory(c, d, Access::Write)?;
+                if name.len() >= buf.len() { return Err(SysError::Invalid); }
+                buf[..name.len()].copy_from_slice(name.as_bytes());
+                buf[name.len()] = 0;
+                Ok(name.len() as u32)
+            },
+            _ => Err(SysError::NoSys)
+        }
+    }
+
+    /// ram accessible by this thread, a range with a memory mapped device in it is a fault.
+    /// with paging the pages have to allow the access and be contiguous in memory, there are no page faults
+    #[allow(clippy::mut_from_ref)]
+    fn guest_memory(&self, addr: u32, len: u32, access: Access) -> Result<&mut [u8], SysError> {
+        let start = self.ram_range(addr, len, access).ok_or(SysError::Fault)? as usize;
+        Ok(unsafe { &mut self.machine.mem_mut()[start..start + len as usize] })
+    }
+
+    /// null terminated utf-8 string, read page by page so it may end before an unmapped page
+    fn guest_str(&self, mut addr: u32) -> Result<String, SysError> {
+        let mut available = self.access_max_addr.saturating_sub(addr).min(MAX_PATH);
+        let mut bytes = vec![];
+        while available > 0 {
+            let chunk = available.min(PAGE_SIZE - addr % PAGE_SIZE);
+            let memory = self.guest_memory(addr, chunk, Access::Read)?;
+            if let Some(len) = memory.iter().position(|b| *b == 0) {
+                bytes.extend_from_slice(&memory[..len]);
+                return String::from_utf8(bytes).map_err(|_| SysError::Invalid);
+            }
+            bytes.extend_from_slice(memory);
+            addr += chunk;
+            available -= chunk;
+        }
+        Err(SysError::Fault)
+    }
+}
@@ -1,11 +1,112 @@
-use crystalvm::{Machine, assemble};
+use std::{fs::File, io::{IsTerminal, Read, Write}, path::PathBuf};
+
+use crystalvm::{Machine, Console, Keyboard, SharedBuffer, KBD_REGS_SIZE, assemble};
+use getch::Getch;
+
+const USAGE: &str = "usage: crystalvm [--seed <u64>] [--input <path>] [--output <path>] [--keyboard <addr>] [<program.casm>]
+  <program.casm>     assembles and runs the program, examples/hello_world.casm by default
+  --seed <u64>       seed the random number generator for reproducible runs
+  --input <path>     read console input from a file or pipe instead of stdin, `-` for stdin
+  --output <path>    write console output to a file or pipe instead of stdout, `-` for stdout
+  --keyboard <addr>  map a keyboard at addr (decimal or 0x hex) and feed it the keys from stdin instead of the console";
+
+/// device id of the keyboard of `--keyboard`
+const DEVICE_KEYBOARD: u32 = 2;
 
 fn main() {
-    assemble("examples/alloc_test.casm", "examples/alloc_test.cstl").unwrap();
-    let machine = Machine::from_image("examples/alloc_test.cstl", 2u32.pow(16));
+    let mut seed = None;
+    let mut input: Option<Box<dyn Read + Send>> = None;
+    let mut output: Option<Box<dyn Write + Send>> = None;
+    let mut keyboard = None;
+    let mut program = PathBuf::from("examples/hello_world.casm");
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--seed" => seed = Some(args.next().and_then(|seed| seed.parse::<u64>().ok()).unwrap_or_else(|| usage("`--seed` expects an unsigned integer"))),
+            "--input" => input = match args.next().unwrap_or_else(|| usage("`--input` expects a path")).as_str() {
+                "-" => None,
+                path => Some(Box::new(File::open(path).unwrap_or_else(|e| {
+                    eprintln!("Unable to open input {path}: {e}");
+                    std::process::exit(1)
+                }))),
+            },
+            "--output" => output = match args.next().unwrap_or_else(|| usage("`--output` expects a path")).as_str() {
+                "-" => None,
+                path => Some(Box::new(File::create(path).unwrap_or_else(|e| {
+                    eprintln!("Unable to open output {path}: {e}");
+                    std::process::exit(1)
+                }))),
+            },
+            "--keyboard" => keyboard = Some(args.next().and_then(|addr| parse_u32(&addr)).unwrap_or_else(|| usage("`--keyboard` expects an address"))),
+            other if !other.starts_with('-') => program = PathBuf::from(other),
+            other => usage(&format!("Unknown argument `{other}`")),
+        }
+    }
+    let image = program.with_extension("cstl");
+    assemble(&program, &image).unwrap();
+    let mut machine = Machine::from_image(&image, 2u32.pow(16)).unwrap_or_else(|e| {
+        eprintln!("Unable to load image: {e}");
+        std::process::exit(1)
+    });
+    if let Some(seed) = seed {
+        machine = machine.with_seed(seed);
+    }
+    // stdin belongs to the keyboard then
+    if keyboard.is_some() && input.is_none() {
+        input = Some(Box::new(SharedBuffer::new()));
+    }
+    if input.is_some() || output.is_some() {
+        let mut console = Console::new();
+        if let Some(input) = input {
+            console = console.with_input(input);
+        }
+        if let Some(output) = output {
+            console = console.with_output(output);
+        }
+        machine = machine.with_console(console);
+    }
+    let mut raw_terminal = None;
+    if let Some(addr) = keyboard {
+        let keyboard = Keyboard::new();
+        machine = machine.with_mmio_device(DEVICE_KEYBOARD, addr..addr.saturating_add(KBD_REGS_SIZE), Box::new(keyboard.clone())).unwrap_or_else(|e| {
+            eprintln!("Unable to map the keyboard: {e}");
+            std::process::exit(1)
+        });
+        raw_terminal = pump_keys(keyboard);
+    }
     println!("Running machine:");
-    machine.run();
-    loop {
-        std::thread::yield_now()
+    let result = machine.run();
+    // restores the terminal, exit skips destructors
+    drop(raw_terminal);
+    let status = result.unwrap_or_else(|e| {
+        eprintln!("Machine crashed: {e}");
+        std::process::exit(1)
+    });
+    println!("Machine exited: {:?} with code {}", status.reason, status.code);
+    std::process::exit(status.code as i32);
+}
+
+fn usage(error: &str) -> ! {
+    eprintln!("{error}\n{USAGE}");
+    std::process::exit(2)
+}
+
+fn parse_u32(s: &str) -> Option<u32> {
+    match s.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
     }
 }
+
+/// feeds stdin to the keyboard on a background thread. a terminal is kept in raw mode while the returned Getch lives,
+/// so keys arrive without enter and escape sequences arrive in one read
+fn pump_keys(keyboard: Keyboard) -> Option<Getch> {
+    let raw_terminal = std::io::stdin().is_terminal().then(Getch::new);
+    std::thread::spawn(move || {
+        let mut buf = [0u8; 64];
+        while let Ok(n @ 1..) = std::io::stdin().read(&mut buf) {
+            keyboard.feed_ansi(&buf[..n]);
+        }
+    });
+    raw_terminal
+}
@@ -0,0 +1,85 @@
+mod common;
+
+use crystalvm::*;
+use common::run;
+
+#[test]
+fn operations_return_the_old_value() {
+    let (status, _) = run(r#"
+st 0x2000 10
+aadd 0x2000 5 %1
+asub 0x2000 3 %2
+aand 0x2000 0x6 %3
+aor 0x2000 0x9 %4
+axchg 0x2000 77 %5
+ld 0x2000 %6
+halt 0
+"#);
+    let r = status.registers;
+    assert_eq!((r[1], r[2], r[3], r[4], r[5], r[6]), (10, 15, 12, 4, 13, 77));
+}
+
+#[test]
+fn compare_and_swap_sets_zero_if_stored() {
+    let (status, _) = run(r#"
+st 0x2000 1
+acas 0x2000 2 3
+mov %F %1
+ld 0x2000 %2
+acas 0x2000 1 3
+mov %F %3
+ld 0x2000 %4
+halt 0
+"#);
+    let r = status.registers;
+    assert_eq!(r[1] & FLAG_BIT_Z, 0);
+    assert_eq!(r[2], 1);
+    assert_ne!(r[3] & FLAG_BIT_Z, 0);
+    assert_eq!(r[4], 3);
+}
+
+#[test]
+fn concurrent_adds_are_not_lost() {
+    let (status, _) = run(r#"
+fork 0x7000 %1
+cmp %1 0
+jz child
+fork 0x7400 %2
+cmp %2 0
+jz child
+fork 0x7800 %3
+cmp %3 0
+jz child
+tch_start %1
+tch_start %2
+tch_start %3
+t_join %1 %4
+t_join %2 %4
+t_join %3 %4
+ld 0x2000 %5
+halt 0
+child:
+mov 1000 %6
+loop:
+aadd 0x2000 1 %7
+sub %6 1 %6
+cmp %6 0
+jnz loop
+halt 0
+"#);
+    assert_eq!(status.registers[5], 3000);
+}
+
+#[test]
+fn atomics_need_permission() {
+    let (status, _) = run(&format!(r#"
+st 0x2000 1
+tch_modpr 0 {perms} {no_atomics}
+aadd 0x2000 1 %1
+mov %F %2
+ld 0x2000 %3
+halt 0
+"#, perms = PR_PERMISSIONS, no_atomics = !PERM_BIT_ATOMIC));
+    assert_ne!(status.registers[2] & FLAG_BIT_E, 0);
+    assert_eq!(status.registers[3], 1);
+}
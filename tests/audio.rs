@@ -0,0 +1,76 @@
+mod common;
+
+use crystalvm::*;
+
+#[test]
+fn wav_is_finished_when_the_machine_is_dropped() {
+    let dir = common::temp_dir("audio");
+    let path = dir.join("out.wav");
+    // 8 frames per tick, so the loop renders a few chunks
+    let audio = Audio::new(8000, 1).with_tick_ns(1_000_000).with_wav(&path).unwrap();
+    let machine = Machine::from_bytes(&common::image(r#"
+st 0xF030 440
+st 0xF034 255
+st 0xF000 1
+mov 2000 %1
+loop:
+sub %1 1 %1
+cmp %1 0
+jnz loop
+halt 0
+"#), 0x10000).unwrap()
+        .with_mmio_device(0x10, 0xF000..0xF000 + AUDIO_REGS_SIZE, Box::new(audio)).unwrap();
+    machine.run().unwrap();
+    drop(machine);
+    let wav = std::fs::read(&path).unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+    let data_len = u32::from_le_bytes(wav[40..44].try_into().unwrap());
+    assert!(data_len > 0);
+    assert_eq!(data_len as usize, wav.len() - 44);
+}
+
+#[test]
+fn hostile_ring_buffer_registers_are_rejected() {
+    let audio = Audio::new(8000, 1).with_tick_ns(1_000_000);
+    let machine = Machine::from_bytes(&common::image(r#"
+mov stack %S
+st 0xF00C 0xFFFFFFF0
+st 0xF010 0xFFFFFFFF
+st 0xF018 0xFFFFFFF0
+st 0xF028 0xFFFFFFFF
+st 0xF020 0x80000001
+st 0xF000 1
+call spin
+ld 0xF010 %1
+ld 0xF018 %2
+st 0xF010 1
+ld 0xF010 %3
+st 0xF010 0x10000
+st 0xF018 0xFFFF
+call spin
+ld 0xF010 %4
+ld 0xF014 %5
+ld 0xF01C %6
+halt 0
+spin:
+mov 200 %10
+loop:
+sub %10 1 %10
+cmp %10 0
+jnz loop
+ret
+@0x1000
+stack:
+"#), 0x10000).unwrap()
+        .with_mmio_device(0x10, 0xF000..0xF000 + AUDIO_REGS_SIZE, Box::new(audio.clone())).unwrap();
+    let r = machine.run().unwrap().registers;
+    // sizes of 0 frames or past the ram are ignored
+    assert_eq!(r[1], 0);
+    assert_eq!(r[2], 0);
+    assert_eq!(r[3], 0);
+    assert_eq!(r[4], 0x10000);
+    // the buffer at the end of the address space can not be read, so it plays silence
+    assert_eq!(r[5], 0);
+    assert!(r[6] > 0);
+    assert!(audio.frames() > 0);
+}
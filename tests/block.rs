@@ -0,0 +1,119 @@
+mod common;
+
+use std::path::PathBuf;
+
+use crystalvm::*;
+
+/// a temporary disk image, removed again on drop
+struct Disk {
+    dir: PathBuf,
+}
+
+impl Disk {
+    fn new() -> Self {
+        Self { dir: common::temp_dir("block") }
+    }
+
+    fn path(&self) -> PathBuf {
+        self.dir.join("disk.img")
+    }
+}
+
+impl Drop for Disk {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// runs src with the block device mapped at 0xF000
+fn run(src: &str, device: BlockDevice) -> Machine {
+    let (machine, _) = common::machine(src);
+    let machine = machine.with_mmio_device(0x10, 0xF000..0xF000 + BLOCK_REGS_SIZE, Box::new(device)).unwrap();
+    machine.run().unwrap();
+    machine
+}
+
+/// code which executes cmd on count sectors at lba, with ram at dma, and stores the status to dst
+fn command(cmd: u32, lba: u32, count: u32, dma: u32, dst: u32) -> String {
+    format!("st 0xF004 {lba}\nst 0xF008 {count}\nst 0xF00C {dma}\nst 0xF000 {cmd}\nld 0xF010 %{dst}\n")
+}
+
+#[test]
+fn sectors_are_written_and_read_back() {
+    let disk = Disk::new();
+    let pattern = (0..SECTOR_SIZE).map(|i| i as u8).collect::<Vec<_>>();
+    let src = command(BLOCK_CMD_WRITE, 1, 1, 0x3000, 1) + &command(BLOCK_CMD_READ, 1, 1, 0x4000, 2)
+        + &command(BLOCK_CMD_FLUSH, 0, 0, 0, 3) + "halt 0\n";
+    let (machine, _) = common::machine(&src);
+    machine.write_memory(0x3000, &pattern).unwrap();
+    let device = BlockDevice::create(disk.path(), 4).unwrap();
+    assert_eq!(device.sectors(), 4);
+    let machine = machine.with_mmio_device(0x10, 0xF000..0xF000 + BLOCK_REGS_SIZE, Box::new(device)).unwrap();
+    let status = machine.run().unwrap();
+    assert_eq!(status.registers[1..4], [BLOCK_STATUS_DONE; 3]);
+    let mut read = vec![0; SECTOR_SIZE as usize];
+    machine.read_memory(0x4000, &mut read).unwrap();
+    assert_eq!(read, pattern);
+    let file = std::fs::read(disk.path()).unwrap();
+    assert_eq!(file.len(), 4 * SECTOR_SIZE as usize);
+    assert_eq!(file[SECTOR_SIZE as usize..2 * SECTOR_SIZE as usize], pattern);
+    assert!(file[..SECTOR_SIZE as usize].iter().all(|b| *b == 0));
+}
+
+#[test]
+fn partial_last_sector_reads_as_zeros() {
+    let disk = Disk::new();
+    std::fs::write(disk.path(), [0xAB; 700]).unwrap();
+    let device = BlockDevice::open(disk.path()).unwrap();
+    assert_eq!(device.sectors(), 2);
+    let src = "st 0x33FC 0xFFFFFFFF\n".to_string() + &command(BLOCK_CMD_READ, 0, 2, 0x3000, 1) + "ld 0xF014 %2\nhalt 0\n";
+    let machine = run(&src, device);
+    let r = machine.registers(0).unwrap();
+    assert_eq!((r[1], r[2]), (BLOCK_STATUS_DONE, 2));
+    // the file ends at 0x3000 + 700
+    assert_eq!(machine.read_u32(0x32B8).unwrap(), 0xABABABAB);
+    assert_eq!(machine.read_u32(0x32BC).unwrap(), 0);
+    assert_eq!(machine.read_u32(0x33FC).unwrap(), 0);
+}
+
+#[test]
+fn invalid_transfers_fail() {
+    let disk = Disk::new();
+    let src = command(BLOCK_CMD_READ, 3, 2, 0x3000, 1)
+        + &command(BLOCK_CMD_READ, u32::MAX, 2, 0x3000, 2)
+        + &command(BLOCK_CMD_WRITE, 0, 1, 0xFF00, 3)
+        + &command(BLOCK_CMD_READ, 0, 1, 0xFF00, 4)
+        + &command(7, 0, 1, 0x3000, 5)
+        + "st 0xF010 0\nld 0xF010 %6\nhalt 0\n";
+    let machine = run(&src, BlockDevice::create(disk.path(), 4).unwrap());
+    let r = machine.registers(0).unwrap();
+    assert_eq!(r[1..6], [BLOCK_STATUS_ERROR; 5]);
+    assert_eq!(r[6], BLOCK_STATUS_IDLE);
+}
+
+#[test]
+fn completion_raises_the_interrupt() {
+    let disk = Disk::new();
+    let src = format!(r#"
+mov vectors %V
+mov 4 %M
+mov stack %S
+st 0xF018 {irq}
+ei
+{}
+halt 0
+handler:
+ld 0xF010 %2
+halt 7
+vectors:
+.u32 0
+.u32 0
+.u32 handler
+@0x1000
+stack:
+"#, command(BLOCK_CMD_READ, 0, 1, 0x3000, 1), irq = BLOCK_IRQ_ENABLE | 2);
+    let machine = run(&src, BlockDevice::create(disk.path(), 1).unwrap());
+    let status = machine.exit_status(0).unwrap().unwrap();
+    assert_eq!(status.code, 7);
+    assert_eq!(status.registers[2], BLOCK_STATUS_DONE);
+}
@@ -0,0 +1,58 @@
+mod common;
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crystalvm::*;
+
+const SRC: &str = r#"
+ld 0xF000 %1
+ld 0xF004 %2
+noop
+noop
+ld 0xF000 %3
+ld 0xF008 %4
+ld 0xF00C %5
+ld 0xF010 %6
+ld 0xF014 %7
+halt 0
+"#;
+
+/// runs `SRC` with the clock mapped at 0xF000 and returns the registers of the main thread
+fn run(clock: Clock, scheduler: Scheduler) -> [u32;64] {
+    let (machine, _) = common::machine(SRC);
+    let machine = machine.with_mmio_device(0x10, 0xF000..0xF000 + CLOCK_REGS_SIZE, Box::new(clock)).unwrap()
+        .with_scheduler(scheduler);
+    machine.run().unwrap().registers
+}
+
+#[test]
+fn virtual_time_follows_the_ticks() {
+    let deterministic = Scheduler::Deterministic { time_slice: 10, seed: 0 };
+    let clock = || Clock::new().with_tick_ns(7).with_epoch(0x1_0000_0005);
+    let r = run(clock(), deterministic);
+    // 4 instructions from the first to the second read
+    assert_eq!(r[3] - r[1], 4 * 7);
+    assert_eq!(r[2], 0);
+    // the wall clock starts at the epoch, latched with the low word
+    assert_eq!((r[4], r[5]), (5, 1));
+    assert_eq!(r[6], r[3] + 7);
+    assert_eq!(r[7], 7);
+    assert_eq!(r, run(clock(), deterministic));
+    // the default source picks virtual time for the deterministic scheduler only
+    assert_eq!(run(clock().with_source(ClockSource::Virtual), Scheduler::Threaded)[7], 7);
+}
+
+#[test]
+fn host_time_follows_the_host_clock() {
+    let before = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+    let r = run(Clock::new(), Scheduler::Threaded);
+    let after = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+    // a few instructions take far less than 4 seconds
+    assert_eq!(r[2], 0);
+    assert!(r[3] >= r[1]);
+    let wall = (r[5] as u64) << 32 | r[4] as u64;
+    assert!(wall >= before && wall <= after);
+    assert!(r[6] < 1_000_000_000);
+    assert_eq!(r[7], 0);
+    assert_eq!(run(Clock::new().with_source(ClockSource::Host), Scheduler::Deterministic { time_slice: 10, seed: 0 })[7], 0);
+}
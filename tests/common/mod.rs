@@ -0,0 +1,57 @@
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use crystalvm::*;
+
+/// a fresh directory in the system temp dir, unique per process and call
+pub fn temp_dir(name: &str) -> std::path::PathBuf {
+    static COUNTER: AtomicU32 = AtomicU32::new(0);
+    let dir = std::env::temp_dir().join(format!("crystalvm-{name}-{}-{}", std::process::id(), COUNTER.fetch_add(1, Ordering::Relaxed)));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// assembles src into an image
+pub fn image(src: &str) -> Vec<u8> {
+    let dir = temp_dir("asm");
+    let (casm, cstl) = (dir.join("test.casm"), dir.join("test.cstl"));
+    std::fs::write(&casm, src).unwrap();
+    assemble(casm.to_str().unwrap(), cstl.to_str().unwrap()).unwrap();
+    let image = std::fs::read(&cstl).unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+    image
+}
+
+/// a machine running src with 64 KiB of memory and its console output captured in the returned buffer
+#[allow(dead_code)]
+pub fn machine(src: &str) -> (Machine, SharedBuffer) {
+    let output = SharedBuffer::new();
+    let machine = Machine::from_bytes(&image(src), 0x10000).unwrap()
+        .with_console(Console::from_streams(Box::new(SharedBuffer::new()), Box::new(output.clone())));
+    (machine, output)
+}
+
+/// runs src until the main thread stops, returns its exit status and everything printed to the console
+#[allow(dead_code)]
+pub fn run(src: &str) -> (ExitStatus, String) {
+    let (machine, output) = machine(src);
+    let status = machine.run().unwrap();
+    (status, output.contents())
+}
+
+/// entry of a page which allows every access of supervisors
+#[allow(dead_code)]
+pub const RWX: u32 = PTE_PRESENT | PTE_READ | PTE_WRITE | PTE_EXECUTE;
+
+/// identity maps pages 0x0 to 0x9 with a page directory at 0x8000 and a page table at 0x9000,
+/// entries overrides the page table entries of single pages. the returned code has to run before %P is set
+#[allow(dead_code)]
+pub fn map_pages(entries: &[(u32, u32)]) -> String {
+    let mut code = String::new();
+    for page in 0..10 {
+        let entry = entries.iter().find(|(p, _)| *p == page).map_or((page * PAGE_SIZE) | RWX, |(_, e)| *e);
+        if entry != 0 {
+            code += &format!("st 0x{:X} 0x{:X}\n", 0x9000 + page * 4, entry);
+        }
+    }
+    code + "st 0x8000 0x9001\n"
+}
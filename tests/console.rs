@@ -0,0 +1,150 @@
+mod common;
+
+use std::{io::{Read, Write}, time::{Duration, Instant}};
+
+use crystalvm::*;
+
+/// input which only returns `x` once the output is not empty anymore, EOF after a second
+struct AfterOutput(SharedBuffer);
+
+impl Read for AfterOutput {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let start = Instant::now();
+        while self.0.is_empty() {
+            if start.elapsed() > Duration::from_secs(1) { return Ok(0); }
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        buf[0] = b'x';
+        Ok(1)
+    }
+}
+
+/// output whose flush always fails
+struct FailingFlush;
+
+impl Write for FailingFlush {
+    fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Err(std::io::Error::other("flush failed"))
+    }
+}
+
+#[test]
+fn waiting_for_input_does_not_stall_output() {
+    let output = SharedBuffer::new();
+    let machine = Machine::from_bytes(&common::image(r#"
+fork 0x7000 %1
+cmp %1 0
+jz child
+tch_start %1
+wait:
+ld 0x6000 %2
+cmp %2 0
+jz wait
+mov 10000 %3
+delay:
+sub %3 1 %3
+cmp %3 0
+jnz delay
+write_stdout 65
+flush_stdout
+t_join %1 %4
+halt 0
+child:
+st 0x6000 1
+read_stdin %1
+halt %1
+"#), 0x10000).unwrap()
+        .with_console(Console::from_streams(Box::new(AfterOutput(output.clone())), Box::new(output.clone())));
+    let status = machine.run().unwrap();
+    assert_eq!(output.contents(), "A");
+    assert_eq!(status.registers[4], b'x' as u32);
+}
+
+#[test]
+fn failed_flush_sets_error() {
+    let machine = Machine::from_bytes(&common::image(r#"
+flush_stdout
+mov %F %1
+mov 0 %F
+dev_flush 0
+mov %F %2
+halt 0
+"#), 0x10000).unwrap()
+        .with_console(Console::from_streams(Box::new(SharedBuffer::new()), Box::new(FailingFlush)));
+    let r = machine.run().unwrap().registers;
+    assert_ne!(r[1] & FLAG_BIT_E, 0);
+    assert_ne!(r[2] & FLAG_BIT_E, 0);
+}
+
+#[test]
+fn scripted_input_is_echoed_until_it_ends() {
+    let input = SharedBuffer::new();
+    input.push(b"hi\n");
+    let output = SharedBuffer::new();
+    let machine = Machine::from_bytes(&common::image(r#"
+loop:
+read_stdin %1
+cmp %1 0
+jz done
+write_stdout %1
+add %2 1 %2
+jmp loop
+done:
+write_stdout 0x20AC
+flush_stdout
+halt %2
+"#), 0x10000).unwrap()
+        .with_console(Console::from_streams(Box::new(input.clone()), Box::new(output.clone())));
+    let status = machine.run().unwrap();
+    assert_eq!(status.registers[2], 3);
+    assert_eq!(output.contents(), "hi\n€");
+    assert!(input.is_empty());
+}
+
+#[test]
+fn invalid_chars_set_error_and_print_a_replacement() {
+    let (status, output) = common::run(r#"
+write_stdout 0xD800
+mov %F %1
+mov 0 %F
+write_stdout 0x110000
+mov %F %2
+mov 0 %F
+write_stdout 65
+mov %F %3
+halt 0
+"#);
+    let r = status.registers;
+    assert_ne!(r[1] & FLAG_BIT_E, 0);
+    assert_ne!(r[2] & FLAG_BIT_E, 0);
+    assert_eq!(r[3] & FLAG_BIT_E, 0);
+    assert_eq!(output, "\u{FFFD}\u{FFFD}A");
+}
+
+#[test]
+fn files_can_be_used_as_streams() {
+    let dir = common::temp_dir("console");
+    std::fs::write(dir.join("input"), "ab").unwrap();
+    let console = Console::new()
+        .with_input(Box::new(std::fs::File::open(dir.join("input")).unwrap()))
+        .with_output(Box::new(std::fs::File::create(dir.join("output")).unwrap()));
+    let machine = Machine::from_bytes(&common::image(r#"
+read_stdin %1
+read_stdin %2
+read_stdin %3
+write_stdout %2
+write_stdout %1
+flush_stdout
+halt %3
+"#), 0x10000).unwrap()
+        .with_console(console);
+    let status = machine.run().unwrap();
+    assert_eq!(status.registers[3], 0);
+    drop(machine);
+    assert_eq!(std::fs::read_to_string(dir.join("output")).unwrap(), "ba");
+    std::fs::remove_dir_all(&dir).unwrap();
+}
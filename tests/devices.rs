@@ -0,0 +1,137 @@
+mod common;
+
+use std::sync::{Arc, Mutex};
+
+use crystalvm::*;
+
+/// what the guest did with a `Recorder`
+#[derive(Default)]
+struct Record {
+    written: Vec<u8>,
+    flushes: u32,
+    resets: u32,
+}
+
+/// device which records all writes and reads back the bytes of a counter
+#[derive(Clone, Default)]
+struct Recorder {
+    record: Arc<Mutex<Record>>,
+    bus: Option<Bus>,
+    next: u8,
+}
+
+impl Device for Recorder {
+    fn read8(&mut self) -> u8 {
+        self.next += 1;
+        self.next
+    }
+
+    fn write8(&mut self, data: u8) {
+        self.record.lock().unwrap().written.push(data);
+        // 0xAA is copied to ram and announced with interrupt 0 on the main thread
+        if data == 0xAA && let Some(bus) = &self.bus {
+            bus.write_memory(0x2000, &[data]).unwrap();
+            bus.raise_interrupt(0, 0).unwrap();
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.record.lock().unwrap().flushes += 1;
+        Ok(())
+    }
+
+    fn attach(&mut self, bus: Bus) {
+        self.bus = Some(bus);
+    }
+
+    fn reset(&mut self) {
+        self.next = 0;
+        self.record.lock().unwrap().resets += 1;
+    }
+}
+
+#[test]
+fn guests_use_custom_devices() {
+    let recorder = Recorder::default();
+    let (machine, _) = common::machine(r#"
+dev_write8 7 1
+dev_write 7 0x02030405
+dev_read8 7 %1
+dev_read 7 %2
+dev_flush 7
+halt 0
+"#);
+    let machine = machine.with_device(7, Box::new(recorder.clone()));
+    let status = machine.run().unwrap();
+    // words are transferred most significant byte first
+    assert_eq!(status.registers[1], 1);
+    assert_eq!(status.registers[2], 0x02030405);
+    let record = recorder.record.lock().unwrap();
+    assert_eq!(record.written, [1, 2, 3, 4, 5]);
+    assert_eq!((record.flushes, record.resets), (1, 1));
+}
+
+#[test]
+fn devices_reach_the_machine_through_their_bus() {
+    let (machine, _) = common::machine(r#"
+mov vectors %V
+mov 1 %M
+mov stack %S
+ei
+dev_write8 7 0xAA
+halt 0
+handler:
+ld8 0x2000 %1
+halt %1
+vectors:
+.u32 handler
+@0x1000
+stack:
+"#);
+    let machine = machine.with_device(7, Box::new(Recorder::default()));
+    assert_eq!(machine.run().unwrap().code, 0xAA);
+}
+
+#[test]
+fn devices_are_replaced() {
+    let (first, second) = (Recorder::default(), Recorder::default());
+    let (machine, _) = common::machine("dev_write8 7 1\nhalt 0\n");
+    let machine = machine.with_device(7, Box::new(first.clone())).with_device(7, Box::new(second.clone()));
+    machine.run().unwrap();
+    assert!(first.record.lock().unwrap().written.is_empty());
+    assert_eq!(second.record.lock().unwrap().written, [1]);
+}
+
+#[test]
+fn devices_can_not_be_added_while_threads_run() {
+    let (machine, _) = common::machine(r#"
+fork 0x7000 %1
+cmp %1 0
+jz child
+tch_start %1
+t_kill 0
+child:
+jmp child
+"#);
+    let status = machine.run().unwrap();
+    assert_eq!(status.reason, ExitReason::Terminated);
+    let added = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| machine.with_device(0x10, Box::new(Timer::new()))));
+    assert!(added.is_err());
+}
+
+#[test]
+fn devices_can_be_added_once_threads_stopped() {
+    let (machine, _) = common::machine(r#"
+fork 0x7000 %1
+cmp %1 0
+jz child
+tch_start %1
+halt 0
+child:
+jmp child
+"#);
+    machine.run().unwrap();
+    // the child stops because the main thread halted
+    let machine = machine.with_device(0x10, Box::new(Timer::new()));
+    assert_eq!(machine.thread_ids(), vec![0, 1]);
+}
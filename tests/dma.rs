@@ -0,0 +1,121 @@
+mod common;
+
+use crystalvm::*;
+
+/// code which writes a descriptor to addr and queues it, %F of the dma instruction ends up in reg
+fn transfer(addr: u32, mode: u32, src: u32, dst: u32, len: u32, irq: u32, reg: u32) -> String {
+    format!("st 0x{addr:X} {mode}\nst 0x{:X} 0x{src:X}\nst 0x{:X} 0x{dst:X}\nst 0x{:X} {len}\nst 0x{:X} 0x{irq:X}\nmov 0 %F\ndma 0x{addr:X}\nmov %F %{reg}\n",
+        addr + DMA_DESC_SRC, addr + DMA_DESC_DST, addr + DMA_DESC_LEN, addr + DMA_DESC_IRQ)
+}
+
+/// code which waits until the status of the descriptor at addr is not busy anymore
+fn wait(addr: u32) -> String {
+    format!("wait_{addr:X}:\nld 0x{:X} %20\ncmp %20 {DMA_STATUS_BUSY}\njz wait_{addr:X}\n", addr + DMA_DESC_STATUS)
+}
+
+#[test]
+fn copies_complete_with_status_and_interrupt() {
+    let (machine, _) = common::machine(&format!(r#"
+mov vectors %V
+mov 1 %M
+mov stack %S
+{}
+ld 0x5010 %2
+ei
+sleep:
+cmp %10 0
+jnz done
+wfi
+jmp sleep
+done:
+ld 0x5010 %3
+halt 0
+handler:
+add %10 1 %10
+reti
+vectors:
+.u32 handler
+@0x1000
+stack:
+"#, transfer(0x5000, DMA_MEM_TO_MEM, 0x4000, 0x4100, 64, DMA_IRQ_ENABLE, 1)));
+    let machine = machine.with_dma_rate(8).with_scheduler(Scheduler::Deterministic { time_slice: 10, seed: 0 });
+    let data: Vec<u8> = (0..64).collect();
+    machine.write_memory(0x4000, &data).unwrap();
+    let r = machine.run().unwrap().registers;
+    assert_eq!(r[1] & FLAG_BIT_E, 0);
+    assert_eq!(r[2], DMA_STATUS_BUSY);
+    assert_eq!(r[3], DMA_STATUS_DONE);
+    assert_eq!(r[10], 1);
+    let mut copy = [0u8; 64];
+    machine.read_memory(0x4100, &mut copy).unwrap();
+    assert_eq!(copy[..], data[..]);
+    // 8 bytes per tick
+    assert!(machine.ticks() >= 8);
+}
+
+#[test]
+fn overlapping_copies_act_like_a_temporary_buffer() {
+    let (machine, _) = common::machine(&format!("{}{}{}{}halt 0\n",
+        transfer(0x5000, DMA_MEM_TO_MEM, 0x4000, 0x4004, 16, 0, 1), wait(0x5000),
+        transfer(0x5020, DMA_MEM_TO_MEM, 0x4104, 0x4100, 16, 0, 2), wait(0x5020)));
+    let data: Vec<u8> = (0..20).collect();
+    machine.write_memory(0x4000, &data).unwrap();
+    machine.write_memory(0x4100, &data).unwrap();
+    machine.run().unwrap();
+    let (mut forwards, mut backwards) = ([0u8; 20], [0u8; 20]);
+    machine.read_memory(0x4000, &mut forwards).unwrap();
+    machine.read_memory(0x4100, &mut backwards).unwrap();
+    assert_eq!(forwards[4..], data[..16]);
+    assert_eq!(backwards[..16], data[4..]);
+}
+
+#[test]
+fn devices_can_be_sources_and_destinations() {
+    let (machine, output) = common::machine(&format!("{}{}{}{}halt 0\n",
+        transfer(0x5000, DMA_MEM_TO_DEVICE, 0x4000, DEVICE_CONSOLE, 5, 0, 1), wait(0x5000),
+        transfer(0x5020, DMA_DEVICE_TO_MEM, DEVICE_RNG, 0x4100, 3, 0, 2), wait(0x5020)));
+    let machine = machine.with_seed(3);
+    machine.write_memory(0x4000, b"hello").unwrap();
+    let status = machine.run().unwrap();
+    assert_eq!(status.registers[1] & FLAG_BIT_E, 0);
+    assert_eq!(output.contents(), "hello");
+    let mut rng = Rng::seeded(3);
+    let expected: Vec<u8> = (0..3).map(|_| rng.read8()).collect();
+    let mut bytes = [0u8; 3];
+    machine.read_memory(0x4100, &mut bytes).unwrap();
+    assert_eq!(bytes[..], expected[..]);
+}
+
+#[test]
+fn invalid_transfers_are_rejected() {
+    let (machine, _) = common::machine(&format!("{}ld 0x5010 %11\n{}ld 0x5030 %12\n{}ld 0x5050 %13\n{}ld 0x5070 %14\ntch_modpr 0 {PR_ACCESS_MAX} 0x8000\n{}ld 0x5090 %15\nmov 0 %F\ndma 0xFFF0\nmov %F %6\nhalt 0\n",
+        // beyond the end of memory
+        transfer(0x5000, DMA_MEM_TO_MEM, 0x4000, 0xFFF0, 0x20, 0, 1),
+        // on a memory mapped device
+        transfer(0x5020, DMA_MEM_TO_MEM, 0x4000, 0xE000, 4, 0, 2),
+        // an unknown device and mode
+        transfer(0x5040, DMA_MEM_TO_DEVICE, 0x4000, 99, 4, 0, 3),
+        transfer(0x5060, 7, 0x4000, 0x4100, 4, 0, 4),
+        // outside the access range of the thread
+        transfer(0x5080, DMA_MEM_TO_MEM, 0x4000, 0x9000, 4, 0, 5)));
+    let machine = machine.with_mmio_device(0x10, 0xE000..0xE000 + RNG_REGS_SIZE, Box::new(Rng::seeded(0))).unwrap();
+    let r = machine.run().unwrap().registers;
+    for (reg, flags) in r.iter().enumerate().take(7).skip(1) {
+        assert_ne!(flags & FLAG_BIT_E, 0, "%{reg}");
+    }
+    assert_eq!(r[11..=15], [DMA_STATUS_ERROR; 5]);
+}
+
+#[test]
+fn a_full_queue_rejects_transfers() {
+    let mut src = String::new();
+    for i in 0..=DMA_QUEUE_LEN as u32 {
+        src += &transfer(0x5000 + i * DMA_DESC_SIZE, DMA_MEM_TO_MEM, 0x4000, 0x6000, 0x400, 0, 20 + i);
+    }
+    let (machine, _) = common::machine(&(src + &format!("ld 0x{:X} %1\nhalt 0\n", 0x5000 + DMA_QUEUE_LEN as u32 * DMA_DESC_SIZE + DMA_DESC_STATUS)));
+    let machine = machine.with_dma_rate(1).with_scheduler(Scheduler::Deterministic { time_slice: 10, seed: 0 });
+    let r = machine.run().unwrap().registers;
+    assert!(r[20..20 + DMA_QUEUE_LEN].iter().all(|f| f & FLAG_BIT_E == 0));
+    assert_ne!(r[20 + DMA_QUEUE_LEN] & FLAG_BIT_E, 0);
+    assert_eq!(r[1], DMA_STATUS_ERROR);
+}
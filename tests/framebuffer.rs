@@ -0,0 +1,95 @@
+mod common;
+
+use crystalvm::*;
+
+/// crc of png chunks, computed bytewise with a table unlike the device
+fn crc32(data: &[u8]) -> u32 {
+    let table = (0..256u32).map(|n| (0..8).fold(n, |c, _| if c & 1 != 0 { 0xEDB88320 ^ (c >> 1) } else { c >> 1 })).collect::<Vec<_>>();
+    !data.iter().fold(!0u32, |c, b| table[((c ^ *b as u32) & 0xFF) as usize] ^ (c >> 8))
+}
+
+/// decodes a png of stored deflate blocks with rgb pixels and filter type none, checking every checksum
+fn decode_png(png: &[u8]) -> Image {
+    assert_eq!(png[..8], *b"\x89PNG\r\n\x1a\n");
+    let (mut rest, mut header, mut idat) = (&png[8..], None, vec![]);
+    while !rest.is_empty() {
+        let len = u32::from_be_bytes(rest[..4].try_into().unwrap()) as usize;
+        let (ty, data) = (&rest[4..8], &rest[8..8 + len]);
+        assert_eq!(u32::from_be_bytes(rest[8 + len..12 + len].try_into().unwrap()), crc32(&rest[4..8 + len]));
+        match ty {
+            b"IHDR" => header = Some(data.to_vec()),
+            b"IDAT" => idat.extend_from_slice(data),
+            b"IEND" => assert_eq!(len, 0),
+            _ => panic!("unexpected chunk"),
+        }
+        rest = &rest[12 + len..];
+    }
+    let header = header.unwrap();
+    let (width, height) = (u32::from_be_bytes(header[..4].try_into().unwrap()), u32::from_be_bytes(header[4..8].try_into().unwrap()));
+    assert_eq!(header[8..], [8, 2, 0, 0, 0]);
+    // zlib header, then stored blocks
+    assert_eq!((idat[0] as u32 * 256 + idat[1] as u32) % 31, 0);
+    let (mut pos, mut raw) = (2, vec![]);
+    loop {
+        let last = idat[pos] & 1 != 0;
+        assert_eq!(idat[pos] & 0b110, 0);
+        let len = u16::from_le_bytes([idat[pos + 1], idat[pos + 2]]);
+        assert_eq!(!len, u16::from_le_bytes([idat[pos + 3], idat[pos + 4]]));
+        raw.extend_from_slice(&idat[pos + 5..pos + 5 + len as usize]);
+        pos += 5 + len as usize;
+        if last { break; }
+    }
+    let (a, b) = raw.iter().fold((1u32, 0u32), |(a, b), x| ((a + *x as u32) % 65521, (b + (a + *x as u32) % 65521) % 65521));
+    assert_eq!(idat[pos..], (b << 16 | a).to_be_bytes());
+    let mut image = Image::new(width, height);
+    image.pixels.clear();
+    for row in raw.chunks(width as usize * 3 + 1) {
+        assert_eq!(row[0], 0);
+        image.pixels.extend_from_slice(&row[1..]);
+    }
+    assert_eq!(image.pixels.len(), (width * height * 3) as usize);
+    image
+}
+
+#[test]
+fn png_dumps_decode_to_the_presented_frame() {
+    let dir = common::temp_dir("framebuffer");
+    let framebuffer = Framebuffer::new(3, 2, PixelFormat::Rgb565).with_base(0x8000).with_dumps(&dir, 2, DumpFormat::Png);
+    let machine = Machine::from_bytes(&common::image(r#"
+st 0x8000 0x07E0F800
+st 0x8004 0xFFFF001F
+st 0x8008 0x84100000
+st 0xF010 1
+st 0x8008 0x84101234
+st 0xF010 1
+ld 0xF014 %1
+halt 0
+"#), 0x10000).unwrap()
+        .with_mmio_device(0x10, 0xF000..0xF000 + FB_REGS_SIZE, Box::new(framebuffer.clone())).unwrap();
+    assert_eq!(machine.run().unwrap().registers[1], 2);
+    // only every second frame is dumped
+    assert!(!dir.join("frame_000001.png").exists());
+    let png = std::fs::read(dir.join("frame_000002.png")).unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+    let frame = framebuffer.frame();
+    assert_eq!(decode_png(&png), frame);
+    assert_eq!(frame.pixel(0, 0), [0xFF, 0, 0]);
+    assert_eq!(frame.pixel(1, 0), [0, 0xFF, 0]);
+    assert_eq!(frame.pixel(2, 0), [0, 0, 0xFF]);
+    assert_eq!(frame.pixel(0, 1), [0xFF, 0xFF, 0xFF]);
+    assert_eq!(frame.pixel(1, 1), [0x10, 0x45, 0xA5]);
+    assert_eq!(frame.pixel(2, 1), [0x84, 0x82, 0x84]);
+}
+
+#[test]
+fn large_png_spans_several_stored_blocks() {
+    let mut image = Image::new(200, 120);
+    for y in 0..120 {
+        for x in 0..200 {
+            image.set_pixel(x, y, [x as u8, y as u8, (x ^ y) as u8]);
+        }
+    }
+    let mut png = vec![];
+    image.write_png(&mut png).unwrap();
+    assert_eq!(decode_png(&png), image);
+}
@@ -0,0 +1,229 @@
+mod common;
+
+use std::path::PathBuf;
+
+use crystalvm::*;
+
+/// a fresh directory with a `root` to mount and an `outside` next to it
+struct Sandbox {
+    dir: PathBuf,
+}
+
+impl Sandbox {
+    fn new() -> Self {
+        let dir = common::temp_dir("fs");
+        std::fs::create_dir_all(dir.join("root/sub")).unwrap();
+        std::fs::create_dir_all(dir.join("outside")).unwrap();
+        std::fs::write(dir.join("root/file"), "inside").unwrap();
+        std::fs::write(dir.join("outside/secret"), "outside").unwrap();
+        Self { dir }
+    }
+
+    fn fs(&self) -> HostFs {
+        HostFs::new(self.dir.join("root")).unwrap()
+    }
+
+    fn read(fs: &mut HostFs, path: &str) -> Result<String, SysError> {
+        let fd = fs.open(path, OPEN_READ)?;
+        let mut buf = [0; 64];
+        let n = fs.read(fd, &mut buf)?;
+        fs.close(fd)?;
+        Ok(String::from_utf8_lossy(&buf[..n as usize]).into_owned())
+    }
+}
+
+impl Drop for Sandbox {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+#[test]
+fn parent_dir_stays_inside() {
+    let sandbox = Sandbox::new();
+    let mut fs = sandbox.fs();
+    assert_eq!(Sandbox::read(&mut fs, "sub/../file"), Ok("inside".into()));
+    assert_eq!(Sandbox::read(&mut fs, "../outside/secret"), Err(SysError::PermissionDenied));
+    assert_eq!(Sandbox::read(&mut fs, "sub/../../outside/secret"), Err(SysError::PermissionDenied));
+    assert_eq!(fs.open("../created", OPEN_WRITE | OPEN_CREATE), Err(SysError::PermissionDenied));
+    assert!(!sandbox.dir.join("created").exists());
+}
+
+#[test]
+fn absolute_paths_are_relative_to_root() {
+    let sandbox = Sandbox::new();
+    let mut fs = sandbox.fs();
+    assert_eq!(Sandbox::read(&mut fs, "/file"), Ok("inside".into()));
+    let outside = sandbox.dir.join("outside/secret");
+    assert_eq!(Sandbox::read(&mut fs, outside.to_str().unwrap()), Err(SysError::NotFound));
+}
+
+#[cfg(unix)]
+#[test]
+fn symlinks_inside_root_are_followed() {
+    let sandbox = Sandbox::new();
+    std::os::unix::fs::symlink(sandbox.dir.join("root/file"), sandbox.dir.join("root/sub/link")).unwrap();
+    std::os::unix::fs::symlink("..", sandbox.dir.join("root/sub/up")).unwrap();
+    let mut fs = sandbox.fs();
+    assert_eq!(Sandbox::read(&mut fs, "sub/link"), Ok("inside".into()));
+    assert_eq!(Sandbox::read(&mut fs, "sub/up/file"), Ok("inside".into()));
+}
+
+#[cfg(unix)]
+#[test]
+fn symlinks_out_of_root_are_denied() {
+    let sandbox = Sandbox::new();
+    std::os::unix::fs::symlink(sandbox.dir.join("outside/secret"), sandbox.dir.join("root/secret")).unwrap();
+    std::os::unix::fs::symlink(sandbox.dir.join("outside"), sandbox.dir.join("root/outside")).unwrap();
+    let mut fs = sandbox.fs();
+    assert_eq!(Sandbox::read(&mut fs, "secret"), Err(SysError::PermissionDenied));
+    assert_eq!(Sandbox::read(&mut fs, "outside/secret"), Err(SysError::PermissionDenied));
+    assert_eq!(fs.open("outside/created", OPEN_WRITE | OPEN_CREATE), Err(SysError::PermissionDenied));
+    assert!(!sandbox.dir.join("outside/created").exists());
+}
+
+#[cfg(unix)]
+#[test]
+fn dangling_symlinks_are_denied() {
+    let sandbox = Sandbox::new();
+    std::os::unix::fs::symlink(sandbox.dir.join("outside/created"), sandbox.dir.join("root/dangling")).unwrap();
+    std::os::unix::fs::symlink(sandbox.dir.join("outside/missing"), sandbox.dir.join("root/dangling_dir")).unwrap();
+    let mut fs = sandbox.fs();
+    assert_eq!(fs.open("dangling", OPEN_WRITE | OPEN_CREATE), Err(SysError::PermissionDenied));
+    assert_eq!(fs.open("dangling_dir/created", OPEN_WRITE | OPEN_CREATE), Err(SysError::PermissionDenied));
+    assert!(!sandbox.dir.join("outside/created").exists());
+}
+
+impl Sandbox {
+    /// a machine running src with the root mounted and a text mode device mapped at 0xF000
+    fn machine(&self, src: &str) -> Machine {
+        let (machine, _) = common::machine(src);
+        machine.with_host_fs(self.dir.join("root")).unwrap()
+            .with_mmio_device(0x10, 0xF000..0xF000 + TEXT_REGS_SIZE, Box::new(TextMode::new(8, 2))).unwrap()
+    }
+}
+
+#[test]
+fn guest_reads_and_writes_files() {
+    let sandbox = Sandbox::new();
+    let machine = sandbox.machine(&format!(r#"
+mov path_file %0
+mov {read} %1
+sys {open}
+mov %0 %10
+mov 0x4000 %1
+mov 64 %2
+sys {read_sys}
+mov %0 %11
+mov %10 %0
+sys {close}
+mov path_new %0
+mov {create} %1
+sys {open}
+mov %0 %10
+mov 0x4000 %1
+mov 6 %2
+sys {write}
+mov %0 %12
+mov %10 %0
+sys {close}
+mov path_sub %0
+mov 0x5000 %1
+sys {stat}
+mov path_root %0
+mov 1 %1
+mov 0x5100 %2
+mov 16 %3
+sys {readdir}
+mov %0 %13
+mov %F %14
+mov %N %15
+halt 0
+path_file:
+.ascii "file\0"
+path_new:
+.ascii "sub/new\0"
+path_sub:
+.ascii "sub\0"
+path_root:
+.ascii "/\0"
+"#, read = OPEN_READ, create = OPEN_WRITE | OPEN_CREATE, open = SYS_OPEN, read_sys = SYS_READ, close = SYS_CLOSE, write = SYS_WRITE,
+        stat = SYS_STAT, readdir = SYS_READDIR));
+    machine.run().unwrap();
+    let r = machine.registers(0).unwrap();
+    assert_eq!(r[11], 6);
+    assert_eq!(r[12], 6);
+    assert_eq!(std::fs::read_to_string(sandbox.dir.join("root/sub/new")).unwrap(), "inside");
+    let mut stat = [0; 8];
+    machine.read_memory(0x5004, &mut stat[..4]).unwrap();
+    assert_eq!(u32::from_le_bytes(stat[..4].try_into().unwrap()), STAT_DIR);
+    // entries are sorted: file, sub
+    assert_eq!(r[13], 3);
+    let mut name = [0; 4];
+    machine.read_memory(0x5100, &mut name).unwrap();
+    assert_eq!(&name, b"sub\0");
+    assert_eq!(r[14] & FLAG_BIT_E, 0);
+    assert_eq!(r[15], 0);
+}
+
+#[test]
+fn failed_syscalls_set_error_and_code() {
+    let sandbox = Sandbox::new();
+    let machine = sandbox.machine(&format!(r#"
+mov path_missing %0
+mov {read} %1
+sys {open}
+mov %F %16
+mov %N %17
+mov 0 %F
+mov 77 %0
+sys {close}
+mov %N %18
+mov 0 %F
+mov path_file %0
+mov {read} %1
+sys {open}
+mov %0 %10
+mov 0xF000 %1
+mov 4 %2
+sys {read_sys}
+mov %N %19
+mov 0 %F
+mov %10 %0
+mov 0xFFF0 %1
+mov 0x20 %2
+sys {read_sys}
+mov %N %20
+mov 0 %F
+sys 99
+mov %N %21
+mov 0 %F
+tch_modpr 0 {perms} {no_fs}
+sys {close}
+mov %F %22
+mov %N %23
+halt 0
+path_missing:
+.ascii "missing\0"
+path_file:
+.ascii "file\0"
+"#, read = OPEN_READ, open = SYS_OPEN, read_sys = SYS_READ, close = SYS_CLOSE, perms = PR_PERMISSIONS, no_fs = !PERM_BIT_HOST_FS));
+    machine.run().unwrap();
+    let r = machine.registers(0).unwrap();
+    assert_ne!(r[16] & FLAG_BIT_E, 0);
+    assert_eq!(r[17], SysError::NotFound as u32);
+    assert_eq!(r[18], SysError::BadFd as u32);
+    // buffers can not point to devices or leave the memory
+    assert_eq!(r[19], SysError::Fault as u32);
+    assert_eq!(r[20], SysError::Fault as u32);
+    assert_eq!(r[21], SysError::NoSys as u32);
+    assert_ne!(r[22] & FLAG_BIT_E, 0);
+    assert_eq!(r[23], SysError::NoSys as u32);
+}
+
+#[test]
+fn syscalls_without_host_fs_fail() {
+    let (status, _) = common::run(&format!("mov 0 %0\nsys {close}\nmov %F %1\nmov %N %2\nhalt 0\n", close = SYS_CLOSE));
+    assert_ne!(status.registers[1] & FLAG_BIT_E, 0);
+    assert_eq!(status.registers[2], SysError::NoSys as u32);
+}
@@ -0,0 +1,43 @@
+mod common;
+
+use crystalvm::*;
+use common::run;
+
+#[test]
+fn reti_does_not_return_from_signal_handler() {
+    let (status, _) = run(r#"
+mov stack %S
+mov handler %H
+t_sig 0 1
+noop
+mov %S %12
+halt 0
+handler:
+add %10 1 %10
+sig_poll %1
+reti
+mov %F %11
+sig_ret
+@0x1000
+stack:
+"#);
+    let r = status.registers;
+    assert_eq!(r[10], 1);
+    assert_eq!(r[1], 1);
+    assert_ne!(r[11] & FLAG_BIT_E, 0);
+    assert_eq!(r[12], 0x1000);
+}
+
+#[test]
+fn vector_table_outside_of_access_range_is_ignored() {
+    let (status, _) = run(&format!(r#"
+tch_modpr 0 {max} 0xF000
+mov 0xFF00 %V
+mov 1 %M
+ei
+int 0
+mov %F %1
+halt 0
+"#, max = PR_ACCESS_MAX));
+    assert_eq!(status.registers[1] & FLAG_BIT_E, 0);
+}
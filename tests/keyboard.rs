@@ -0,0 +1,84 @@
+mod common;
+
+use crystalvm::*;
+
+/// takes every buffered event
+fn events(keyboard: &Keyboard) -> Vec<u32> {
+    let mut keyboard = keyboard.clone();
+    std::iter::from_fn(|| Some(keyboard.read()).filter(|event| *event != 0)).collect()
+}
+
+/// the events of tapping every key
+fn taps(keys: &[(u32, u32)]) -> Vec<u32> {
+    keys.iter().flat_map(|(key, modifiers)| [key | modifiers, key | modifiers | KBD_RELEASE]).collect()
+}
+
+#[test]
+fn chars_and_control_chars() {
+    let keyboard = Keyboard::new();
+    assert!(keyboard.feed_ansi("aB\r\x7f\x03é".as_bytes()));
+    assert_eq!(events(&keyboard), taps(&[
+        ('a' as u32, 0), ('B' as u32, KBD_MOD_SHIFT), (KEY_ENTER, 0), (KEY_BACKSPACE, 0), ('c' as u32, KBD_MOD_CTRL), ('é' as u32, 0),
+    ]));
+}
+
+#[test]
+fn escape_sequences() {
+    let keyboard = Keyboard::new();
+    assert!(keyboard.feed_ansi(b"\x1b[A\x1b[1;5C\x1b[3~\x1b[15~\x1bOP\x1bOH\x1bx\x1b"));
+    assert_eq!(events(&keyboard), taps(&[
+        (KEY_UP, 0), (KEY_RIGHT, KBD_MOD_CTRL), (KEY_DELETE, 0), (KEY_F1 + 4, 0), (KEY_F1, 0), (KEY_HOME, 0),
+        ('x' as u32, KBD_MOD_ALT), (KEY_ESCAPE, 0),
+    ]));
+}
+
+#[test]
+fn cut_off_sequences_are_completed_by_the_next_call() {
+    let keyboard = Keyboard::new();
+    assert!(keyboard.feed_ansi(b"a\x1b[1;"));
+    assert_eq!(events(&keyboard), taps(&[('a' as u32, 0)]));
+    assert!(keyboard.feed_ansi(b"2B\x1bO"));
+    assert_eq!(events(&keyboard), taps(&[(KEY_DOWN, KBD_MOD_SHIFT)]));
+    assert!(keyboard.feed_ansi(b"Q\xC3"));
+    assert_eq!(events(&keyboard), taps(&[(KEY_F1 + 1, 0)]));
+    assert!(keyboard.feed_ansi(b"\xA9"));
+    assert_eq!(events(&keyboard), taps(&[('é' as u32, 0)]));
+}
+
+#[test]
+fn invalid_sequences_are_skipped() {
+    let keyboard = Keyboard::new();
+    // unknown final byte, an intermediate byte, a sequence broken by a control char and invalid utf-8
+    assert!(keyboard.feed_ansi(b"\x1b[9z\x1b[1 A\x1b[1\x01b\xFF"));
+    assert_eq!(events(&keyboard), taps(&[('a' as u32, KBD_MOD_CTRL), ('b' as u32, 0), ('\u{FFFD}' as u32, 0)]));
+}
+
+#[test]
+fn chars_which_are_no_keys_are_skipped() {
+    let keyboard = Keyboard::new();
+    assert!(keyboard.type_text("a\u{1F600}\u{E000}b"));
+    assert!(keyboard.feed_ansi("\u{1F600}\u{E001}c".as_bytes()));
+    assert_eq!(events(&keyboard), taps(&[('a' as u32, 0), ('b' as u32, 0), ('c' as u32, 0)]));
+}
+
+#[test]
+fn guest_polls_events() {
+    let keyboard = Keyboard::new();
+    keyboard.type_text("hi");
+    let machine = Machine::from_bytes(&common::image(r#"
+ld 0xF004 %1
+ld 0xF000 %2
+dev_read8 2 %3
+ld 0xF000 %4
+ld 0xF000 %5
+halt 0
+"#), 0x10000).unwrap()
+        .with_mmio_device(2, 0xF000..0xF000 + KBD_REGS_SIZE, Box::new(keyboard)).unwrap();
+    let r = machine.run().unwrap().registers;
+    assert_eq!(r[1], 4);
+    assert_eq!(r[2], 'h' as u32);
+    // dev_read8 skips the release of h
+    assert_eq!(r[3], 'i' as u32);
+    assert_eq!(r[4], 'i' as u32 | KBD_RELEASE);
+    assert_eq!(r[5], 0);
+}
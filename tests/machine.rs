@@ -0,0 +1,160 @@
+mod common;
+
+use crystalvm::*;
+use common::run;
+
+#[test]
+fn halt_returns_exit_code() {
+    let (status, _) = run("halt 42\n");
+    assert_eq!(status.code, 42);
+    assert_eq!(status.reason, ExitReason::Halted);
+    let (status, _) = run("mov 0xFFFFFFFF %1\nhalt %1\n");
+    assert_eq!(status.code, u32::MAX);
+    assert_eq!(status.registers[1], u32::MAX);
+}
+
+#[test]
+fn halt_stops_only_the_halting_child() {
+    let (machine, _) = common::machine(r#"
+fork 0x7000 %1
+cmp %1 0
+jz child
+tch_start %1
+t_join %1 %2
+halt 3
+child:
+halt 9
+"#);
+    let status = machine.run().unwrap();
+    assert_eq!((status.code, status.reason), (3, ExitReason::Halted));
+    assert_eq!(status.registers[2], 9);
+}
+
+#[test]
+fn main_thread_stopped_without_halt_exits_with_0() {
+    let (status, _) = run("mov 7 %1\nt_kill 0\nhalt 1\n");
+    assert_eq!((status.code, status.reason), (0, ExitReason::Terminated));
+    assert_eq!(status.registers[1], 7);
+}
+
+#[test]
+fn run_returns_after_halt() {
+    let (status, output) = run(r#"
+write_stdout 97
+halt 0
+write_stdout 98
+"#);
+    assert_eq!(status.code, 0);
+    assert_eq!(output, "a");
+}
+
+#[test]
+fn invalid_images_are_errors() {
+    assert!(matches!(Machine::from_bytes(&[], 0x100), Err(VmError::InvalidImage(_))));
+    assert!(matches!(Machine::from_bytes(&[0; 0x200], 0x100), Err(VmError::ImageTooLarge { image_size: 0x200, memory_size: 0x100 })));
+    let dir = common::temp_dir("missing");
+    assert!(matches!(Machine::from_image(dir.join("missing.cstl"), 0x100), Err(VmError::Io(_))));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn invalid_host_accesses_are_errors() {
+    let (machine, _) = common::machine("halt 0\n");
+    assert!(matches!(machine.registers(5), Err(VmError::UnknownThread(5))));
+    assert!(matches!(machine.register(0, NUM_REGS), Err(VmError::InvalidRegister(_))));
+    assert!(matches!(machine.set_register(0, NUM_REGS, 0), Err(VmError::InvalidRegister(_))));
+    assert!(matches!(machine.read_u32(0xFFFE), Err(VmError::OutOfBounds { addr: 0xFFFE, len: 4 })));
+    assert!(matches!(machine.write_memory(0x10000, &[1]), Err(VmError::OutOfBounds { .. })));
+    assert!(matches!(machine.raise_interrupt(0, NUM_INTERRUPTS), Err(VmError::InvalidInterrupt(_))));
+    assert!(matches!(machine.step(3, 1), Err(VmError::UnknownThread(3))));
+    let machine = machine.with_mmio_device(0x10, 0xF000..0xF100, Box::new(Timer::new())).unwrap();
+    assert!(matches!(machine.with_mmio_device(0x11, 0xF0F0..0xF200, Box::new(Timer::new())), Err(VmError::InvalidMapping { start: 0xF0F0, end: 0xF200 })));
+}
+
+#[test]
+fn errors_of_a_guest_do_not_stop_the_machine() {
+    let (status, _) = run(r#"
+div 1 0 %1
+mov %F %2
+dev_read 99 %3
+mov %F %4
+halt 0
+"#);
+    assert_eq!(status.reason, ExitReason::Halted);
+    assert_ne!(status.registers[2] & FLAG_BIT_L, 0);
+    assert_ne!(status.registers[4] & FLAG_BIT_E, 0);
+}
+
+#[test]
+fn step_executes_single_instructions() {
+    let machine = Machine::from_bytes(&common::image(r#"
+mov 1 %1
+add %1 %2 %3
+halt %3
+"#), 0x1000).unwrap();
+    assert_eq!(machine.step(0, 1).unwrap(), 1);
+    assert_eq!(machine.register(0, 1).unwrap(), 1);
+    // the literal follows the instruction
+    assert_eq!(machine.register(0, REG_I).unwrap(), 8);
+    machine.set_register(0, 2, 41).unwrap();
+    assert!(machine.exit_status(0).unwrap().is_none());
+    // the thread halts after 2 of the 5 instructions
+    assert_eq!(machine.step(0, 5).unwrap(), 2);
+    let status = machine.exit_status(0).unwrap().unwrap();
+    assert_eq!((status.code, status.reason), (42, ExitReason::Halted));
+    assert_eq!(machine.step(0, 1).unwrap(), 0);
+}
+
+#[test]
+fn memory_is_shared_with_the_host() {
+    let (machine, _) = common::machine(r#"
+ld 0x2000 %1
+add %1 1 %1
+st 0x2004 %1
+halt 0
+"#);
+    machine.write_u32(0x2000, 0x1234).unwrap();
+    machine.run().unwrap();
+    assert_eq!(machine.read_u32(0x2004).unwrap(), 0x1235);
+    let mut bytes = [0; 4];
+    machine.read_memory(0x2004, &mut bytes).unwrap();
+    assert_eq!(bytes, [0x35, 0x12, 0, 0]);
+}
+
+#[test]
+fn same_seed_runs_the_same() {
+    // two threads print interleaved, the order depends on the lengths of their turns
+    let src = r#"
+fork 0x7000 %1
+cmp %1 0
+jz child
+tch_start %1
+mov 20 %2
+main_loop:
+write_stdout 97
+sub %2 1 %2
+cmp %2 0
+jnz main_loop
+t_join %1 %3
+dev_read 1 %4
+halt 0
+child:
+mov 20 %2
+child_loop:
+write_stdout 98
+sub %2 1 %2
+cmp %2 0
+jnz child_loop
+halt 0
+"#;
+    let run = |seed| {
+        let (machine, output) = common::machine(src);
+        let machine = machine.with_seed(seed).with_scheduler(Scheduler::Deterministic { time_slice: 7, seed });
+        let status = machine.run().unwrap();
+        (output.contents(), status.registers[4], machine.ticks())
+    };
+    let first = run(1);
+    assert_eq!(first, run(1));
+    assert_eq!(first.0.len(), 40);
+    assert_ne!(first.0, run(2).0);
+}
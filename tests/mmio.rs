@@ -0,0 +1,94 @@
+mod common;
+
+use std::sync::{Arc, Mutex};
+
+use crystalvm::*;
+
+/// 16 bytes of registers which act like ram, through the default word accesses of `Device`
+#[derive(Clone, Default)]
+struct Registers {
+    bytes: Arc<Mutex<[u8;16]>>,
+}
+
+impl Device for Registers {
+    fn read8(&mut self) -> u8 { 0 }
+
+    fn write8(&mut self, _data: u8) {}
+
+    fn flush(&mut self) -> std::io::Result<()> { Ok(()) }
+
+    fn load8(&mut self, offset: u32) -> u8 {
+        self.bytes.lock().unwrap()[offset as usize]
+    }
+
+    fn store8(&mut self, offset: u32, data: u8) {
+        self.bytes.lock().unwrap()[offset as usize] = data;
+    }
+}
+
+/// a machine running src with `Registers` mapped at 0xF000
+fn machine(src: &str) -> (Machine, Registers) {
+    let registers = Registers::default();
+    let (machine, _) = common::machine(src);
+    (machine.with_mmio_device(0x10, 0xF000..0xF010, Box::new(registers.clone())).unwrap(), registers)
+}
+
+#[test]
+fn loads_and_stores_go_to_the_device() {
+    let (machine, registers) = machine(r#"
+st 0xF000 0x11223344
+st8 0xF004 0x55
+ld 0xF000 %1
+ld8 0xF001 %2
+ld 0xF002 %3
+halt 0
+"#);
+    let status = machine.run().unwrap();
+    assert_eq!(registers.bytes.lock().unwrap()[..5], [0x44, 0x33, 0x22, 0x11, 0x55]);
+    assert_eq!(status.registers[1], 0x11223344);
+    assert_eq!(status.registers[2], 0x33);
+    assert_eq!(status.registers[3], 0x00551122);
+    // the ram below stays untouched
+    assert_eq!(machine.read_u32(0xF000).unwrap(), 0);
+}
+
+#[test]
+fn words_at_the_end_are_split_between_device_and_ram() {
+    let (machine, registers) = machine(r#"
+st 0xF00E 0xAABBCCDD
+ld 0xF00E %1
+halt 0
+"#);
+    let status = machine.run().unwrap();
+    assert_eq!(registers.bytes.lock().unwrap()[14..], [0xDD, 0xCC]);
+    assert_eq!(machine.read_u32(0xF00C).unwrap(), 0);
+    assert_eq!(machine.read_u32(0xF010).unwrap(), 0xAABB);
+    assert_eq!(status.registers[1], 0xAABBCCDD);
+}
+
+#[test]
+fn mapped_registers_need_device_permission() {
+    let (machine, registers) = machine(&format!(r#"
+tch_modpr 0 {perms} {no_device}
+st 0xF000 1
+mov %F %1
+halt 0
+"#, perms = PR_PERMISSIONS, no_device = !PERM_BIT_DEVICE));
+    let status = machine.run().unwrap();
+    assert_ne!(status.registers[1] & FLAG_BIT_E, 0);
+    assert_eq!(*registers.bytes.lock().unwrap(), [0; 16]);
+}
+
+#[test]
+fn invalid_ranges_can_not_be_mapped() {
+    let (machine, _) = machine("halt 0\n");
+    // empty, reversed, out of memory and overlapping ranges
+    for (start, end) in [(0x100, 0x100), (0x200, 0x100), (0xFFF0, 0x10010), (0xEFF8, 0xF008), (0xF004, 0xF008)] {
+        let mapped = common::machine("halt 0\n").0
+            .with_mmio_device(0x10, 0xF000..0xF010, Box::new(Registers::default())).unwrap()
+            .with_mmio_device(0x11, start..end, Box::new(Registers::default()));
+        assert!(matches!(mapped, Err(VmError::InvalidMapping { start: s, end: e }) if (s, e) == (start, end)));
+    }
+    // right next to another range is fine
+    assert!(machine.with_mmio_device(0x11, 0xF010..0xF020, Box::new(Registers::default())).is_ok());
+}
@@ -0,0 +1,301 @@
+mod common;
+
+use crystalvm::*;
+use common::{run, map_pages, RWX};
+
+#[test]
+fn faulting_immediate_runs_once() {
+    // the immediate of `write_stdout` is the first word of the unmapped page 1
+    let (status, output) = run(&format!(r#"
+mov stack %S
+mov vectors %V
+mov 1 %M
+{}
+mov 0x8000 %P
+ei
+jmp boundary
+handler:
+add %10 1 %10
+mov %A %11
+mov %R %12
+st 0x9004 0x100F
+tlbflush
+reti
+vectors:
+.u32 handler
+@0xFFC
+boundary:
+write_stdout 65
+halt 0
+@0x3000
+stack:
+"#, map_pages(&[(1, 0)])));
+    assert_eq!(output, "A");
+    assert_eq!(status.registers[10], 1);
+    assert_eq!(status.registers[11], 0x1000);
+    assert_eq!(status.registers[12], FAULT_EXECUTE);
+    assert_eq!(status.registers[REG_F as usize] & FLAG_BIT_E, 0);
+}
+
+#[test]
+fn faulting_stack_pop_runs_once() {
+    // page 5 holds the stack and can only be written, so the pop faults but the trap frame can be pushed
+    let (status, output) = run(&format!(r#"
+mov vectors %V
+mov 1 %M
+st 0x5000 66
+{}
+mov 0x8000 %P
+mov 0x5000 %S
+ei
+write_stdout *
+mov %S %13
+halt 0
+handler:
+add %10 1 %10
+mov %A %11
+mov %R %12
+st 0x9014 0x500F
+tlbflush
+reti
+vectors:
+.u32 handler
+"#, map_pages(&[(5, 0x5000 | PTE_PRESENT | PTE_WRITE)])));
+    assert_eq!(output, "B");
+    assert_eq!(status.registers[10], 1);
+    assert_eq!(status.registers[11], 0x5000);
+    assert_eq!(status.registers[12], FAULT_READ | FAULT_PROTECTION);
+    assert_eq!(status.registers[13], 0x4FFC);
+}
+
+#[test]
+fn double_fault_crashes_main_thread() {
+    // the page fault handler can not be entered because the stack is not mapped
+    let (machine, _) = common::machine(&format!(r#"
+mov vectors %V
+mov 1 %M
+{}
+mov 0x8000 %P
+mov 0x5000 %S
+ei
+ld 0x5000 %1
+halt 0
+handler:
+reti
+vectors:
+.u32 handler
+"#, map_pages(&[(5, 0)])));
+    let error = machine.run().unwrap_err();
+    assert!(matches!(&error, VmError::GuestFault { thread_id: 0, message, .. } if message.contains("double fault")), "{error}");
+}
+
+#[test]
+fn double_fault_of_child_is_recorded() {
+    let (machine, _) = common::machine(&format!(r#"
+{}
+mov 0x8000 %P
+fork 0x7000 %1
+cmp %1 0
+jz child
+tch_start %1
+wait:
+t_state %1 %2
+cmp %2 3
+jnz wait
+halt 0
+child:
+mov vectors %V
+mov 1 %M
+mov 0x5000 %S
+ei
+ld 0x5000 %1
+halt 1
+handler:
+reti
+vectors:
+.u32 handler
+"#, map_pages(&[(5, 0)])));
+    let machine = machine.with_scheduler(Scheduler::Deterministic { time_slice: 100, seed: 0 });
+    let main = machine.run().unwrap();
+    let child = machine.exit_status(1).unwrap().unwrap();
+    assert_eq!(child.reason, ExitReason::Faulted);
+    let error = machine.guest_fault(1).unwrap().unwrap();
+    assert!(matches!(&error, VmError::GuestFault { thread_id: 1, message, .. } if message.contains("double fault")), "{error}");
+    assert_eq!(main.reason, ExitReason::Halted);
+    assert!(machine.guest_fault(0).unwrap().is_none());
+}
+
+#[test]
+fn user_threads_only_access_user_pages() {
+    // everything but page 6 can be accessed by the child once it lost the supervisor bit
+    let user = (0..10).filter(|page| *page != 6).map(|page| (page, (page * PAGE_SIZE) | RWX | PTE_USER)).collect::<Vec<_>>();
+    let (machine, _) = common::machine(&format!(r#"
+st 0x5000 7
+st 0x6000 8
+{}
+mov 0x8000 %P
+fork 0x7000 %1
+cmp %1 0
+jz child
+tch_modpr %1 0 0x{:X}
+tch_start %1
+wait:
+t_state %1 %2
+cmp %2 3
+jnz wait
+halt 0
+child:
+ld 0x6000 %4
+mov %F %5
+mov %A %6
+mov %R %7
+mov 0 %F
+ld 0x5000 %8
+mov %F %9
+mov 0 %P
+mov %F %10
+mov %P %11
+halt 0
+"#, map_pages(&user), !PERM_BIT_SUPERVISOR));
+    let machine = machine.with_scheduler(Scheduler::Deterministic { time_slice: 100, seed: 0 });
+    machine.run().unwrap();
+    let r = machine.registers(1).unwrap();
+    assert_eq!(r[4], 0);
+    assert_ne!(r[5] & FLAG_BIT_E, 0);
+    assert_eq!(r[6], 0x6000);
+    assert_eq!(r[7], FAULT_READ | FAULT_PROTECTION);
+    assert_eq!(r[8], 7);
+    assert_eq!(r[9] & FLAG_BIT_E, 0);
+    // only supervisors may change the page table
+    assert_ne!(r[10] & FLAG_BIT_E, 0);
+    assert_eq!(r[11], 0x8000);
+}
+
+#[test]
+fn stale_tlb_entries_are_used_until_flushed() {
+    let (status, _) = run(&format!(r#"
+st 0x5000 7
+{}
+mov 0x8000 %P
+ld 0x5000 %1
+st 0x9014 0
+ld 0x5000 %2
+mov %F %3
+tlbflush
+ld 0x5000 %4
+mov %F %5
+mov %A %6
+halt 0
+"#, map_pages(&[])));
+    let r = status.registers;
+    assert_eq!(r[1], 7);
+    assert_eq!(r[2], 7);
+    assert_eq!(r[3] & FLAG_BIT_E, 0);
+    assert_eq!(r[4], 0);
+    assert_ne!(r[5] & FLAG_BIT_E, 0);
+    assert_eq!(r[6], 0x5000);
+}
+
+#[test]
+fn faulting_atomic_op_runs_once() {
+    // page 5 is read only, so the atomic add faults before it reads or writes anything
+    let (status, _) = run(&format!(r#"
+mov stack %S
+mov vectors %V
+mov 1 %M
+st 0x5000 10
+{}
+mov 0x8000 %P
+ei
+aadd 0x5000 5 %1
+ld 0x5000 %2
+halt 0
+handler:
+add %10 1 %10
+st 0x9014 0x500F
+tlbflush
+reti
+vectors:
+.u32 handler
+@0x3000
+stack:
+"#, map_pages(&[(5, 0x5000 | PTE_PRESENT | PTE_READ)])));
+    let r = status.registers;
+    assert_eq!(r[10], 1);
+    assert_eq!(r[1], 10);
+    assert_eq!(r[2], 15);
+    assert_eq!(r[REG_F as usize] & FLAG_BIT_E, 0);
+}
+
+#[test]
+fn accesses_across_pages_follow_the_mapping() {
+    // virtual page 3 is frame 4, so words at the end of page 2 are split between two frames
+    let (status, _) = run(&format!(r#"
+st 0x1FFC 0x44434241
+st 0x2000 0x48474645
+st 0x2FFC 0x44434241
+st 0x4000 0x48474645
+{}
+mov 0x8000 %P
+ld 0x2FFE %1
+st 0x2FFE 0x11223344
+ld 0x4000 %2
+st 0x5000 {mem_to_mem}
+st 0x5004 0x1FFC
+st 0x5008 0x6000
+st 0x500C 8
+dma 0x5000
+mov %F %3
+st 0x5020 {mem_to_mem}
+st 0x5024 0x2FFC
+st 0x5028 0x6100
+st 0x502C 8
+dma 0x5020
+mov %F %4
+ld 0x5030 %5
+wait:
+ld 0x5010 %6
+cmp %6 {done}
+jnz wait
+ld 0x6000 %7
+ld 0x6004 %8
+halt 0
+"#, map_pages(&[(3, 0x4000 | RWX)]), mem_to_mem = DMA_MEM_TO_MEM, done = DMA_STATUS_DONE));
+    let r = status.registers;
+    assert_eq!(r[1], 0x46454443);
+    assert_eq!(r[2], 0x48471122);
+    // a dma buffer has to be contiguous in memory
+    assert_eq!(r[3] & FLAG_BIT_E, 0);
+    assert_ne!(r[4] & FLAG_BIT_E, 0);
+    assert_eq!(r[5], DMA_STATUS_ERROR);
+    assert_eq!(r[7], 0x44434241);
+    assert_eq!(r[8], 0x48474645);
+}
+
+#[test]
+fn page_table_of_ready_children() {
+    let (status, _) = run(&format!(r#"
+{}
+st 0xA000 0x9001
+mov 0x8000 %P
+fork 0x7000 %1
+cmp %1 0
+jz child
+tch_modpr %1 {pr} 0xA000
+mov %F %2
+tch_getpr %1 {pr} %3
+tch_start %1
+tch_modpr %1 {pr} 0x8000
+mov %F %4
+t_join %1 %5
+halt 0
+child:
+halt %P
+"#, map_pages(&[]), pr = PR_PAGE_TABLE));
+    let r = status.registers;
+    assert_eq!(r[2] & FLAG_BIT_E, 0);
+    assert_eq!(r[3], 0xA000);
+    // running children keep their page table
+    assert_ne!(r[4] & FLAG_BIT_E, 0);
+    assert_eq!(r[5], 0xA000);
+}
@@ -0,0 +1,71 @@
+mod common;
+
+use crystalvm::*;
+
+/// runs src with a seeded machine and a second generator mapped at 0xF000, returns the registers of the main thread
+fn run(src: &str, seed: u64) -> [u32;64] {
+    let (machine, _) = common::machine(src);
+    let machine = machine.with_seed(seed)
+        .with_mmio_device(0x10, 0xF000..0xF000 + RNG_REGS_SIZE, Box::new(Rng::seeded(seed))).unwrap();
+    machine.run().unwrap().registers
+}
+
+#[test]
+fn seeded_values_repeat() {
+    let src = r#"
+dev_read 1 %1
+dev_read 1 %2
+ld 0xF000 %3
+ld 0xF000 %4
+halt 0
+"#;
+    let r = run(src, 5);
+    let mut rng = Rng::seeded(5);
+    let expected = [rng.read(), rng.read()];
+    assert_eq!([r[1], r[2]], expected);
+    // the mapped generator has the same seed
+    assert_eq!([r[3], r[4]], expected);
+    assert_ne!(r[1], r[2]);
+    assert_eq!(r, run(src, 5));
+    assert_ne!(run(src, 6)[1], r[1]);
+}
+
+#[test]
+fn guests_can_seed_the_generator() {
+    let r = run(r#"
+dev_write 1 42
+dev_read 1 %1
+st 0xF004 42
+ld 0xF000 %2
+st8 0xF004 42
+ld 0xF000 %3
+dev_write8 1 42
+dev_read 1 %4
+ld 0xF004 %5
+halt 0
+"#, 5);
+    let mut rng = Rng::seeded(5);
+    rng.write(42);
+    let expected = rng.read();
+    assert_eq!([r[1], r[2], r[3], r[4]], [expected; 4]);
+    // the seed register can not be read
+    assert_eq!(r[5], 0);
+}
+
+#[test]
+fn every_byte_is_fresh() {
+    let r = run(r#"
+dev_read8 1 %1
+dev_read8 1 %2
+ld8 0xF000 %3
+ld8 0xF001 %4
+ld 0xF001 %5
+halt 0
+"#, 5);
+    let mut rng = Rng::seeded(5);
+    let expected: Vec<u32> = (0..5).map(|_| rng.read() & 0xFF).collect();
+    assert_eq!([r[1], r[2]], [expected[0], expected[1]]);
+    assert_eq!([r[3], r[4]], [expected[0], expected[1]]);
+    // an unaligned word is put together from 3 fresh bytes and the first byte of the seed register
+    assert_eq!(r[5], u32::from_le_bytes([expected[2] as u8, expected[3] as u8, expected[4] as u8, 0]));
+}
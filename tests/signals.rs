@@ -0,0 +1,23 @@
+mod common;
+
+use crystalvm::*;
+use common::run;
+
+#[test]
+fn sig_ret_outside_of_signal_handler_is_an_error() {
+    let (status, _) = run(r#"
+mov stack %S
+mov 0x1234 *
+mov %S %1
+sig_ret
+mov %F %2
+mov %S %3
+halt 0
+@0x1000
+stack:
+"#);
+    let r = status.registers;
+    assert_ne!(r[2] & FLAG_BIT_E, 0);
+    assert_eq!(r[3], r[1]);
+    assert_eq!(status.reason, ExitReason::Halted);
+}
@@ -0,0 +1,72 @@
+mod common;
+
+use crystalvm::*;
+
+/// runs src with a 4x2 text mode mapped at 0xF000 and its cells at 0x3000
+fn run(src: &str) -> (TextMode, Machine) {
+    let text = TextMode::new(4, 2).with_base(0x3000);
+    let (machine, _) = common::machine(src);
+    let machine = machine.with_mmio_device(0x10, 0xF000..0xF000 + TEXT_REGS_SIZE, Box::new(text.clone())).unwrap();
+    machine.run().unwrap();
+    (text, machine)
+}
+
+/// code writing every char of s to the putc register
+fn putc(s: &str) -> String {
+    s.bytes().map(|c| format!("st 0x{:X} {c}\n", 0xF000 + TEXT_REG_PUTC)).collect()
+}
+
+fn present() -> String {
+    format!("st 0x{:X} 1\n", 0xF000 + TEXT_REG_PRESENT)
+}
+
+#[test]
+fn putc_wraps_at_the_end_of_a_row() {
+    let (text, machine) = run(&format!("{}{}ld 0x{:X} %1\nld 0x{:X} %2\nhalt 0\n", putc("abcde"), present(),
+        0xF000 + TEXT_REG_CURSOR_X, 0xF000 + TEXT_REG_CURSOR_Y));
+    assert_eq!(text.frame_count(), 1);
+    assert_eq!(text.frame_text(), "abcd\ne   \n");
+    let r = machine.registers(0).unwrap();
+    assert_eq!((r[1], r[2]), (1, 1));
+    let mut cell = [0; 2];
+    machine.read_memory(0x3008, &mut cell).unwrap();
+    assert_eq!(cell, [b'e', TEXT_DEFAULT_ATTR]);
+}
+
+#[test]
+fn control_chars_move_the_cursor() {
+    let (text, _) = run(&format!("{}{}halt 0\n", putc("ab\x08c\rd\ne"), present()));
+    assert_eq!(text.frame_text(), "dc  \ne   \n");
+}
+
+#[test]
+fn putc_scrolls_at_the_bottom() {
+    let (text, machine) = run(&format!("st 0x{:X} 0x1F\n{}{}halt 0\n", 0xF000 + TEXT_REG_ATTR, putc("ab\ncd\nef"), present()));
+    assert_eq!(text.frame_text(), "cd  \nef  \n");
+    // the new row is blank with the current attribute
+    let mut cell = [0; 2];
+    machine.read_memory(0x300C, &mut cell).unwrap();
+    assert_eq!(cell, [b' ', 0x1F]);
+}
+
+#[test]
+fn scroll_register_moves_rows_up() {
+    let scroll = |n: u32| format!("st 0x{:X} {n}\n", 0xF000 + TEXT_REG_SCROLL);
+    let (text, _) = run(&format!("{}{}{}halt 0\n", putc("ab\ncd"), scroll(1), present()));
+    assert_eq!(text.frame_text(), "cd  \n    \n");
+    let (text, _) = run(&format!("{}{}{}halt 0\n", putc("ab\ncd"), scroll(100), present()));
+    assert_eq!(text.frame_text(), "    \n    \n");
+}
+
+#[test]
+fn cursor_stays_on_the_screen() {
+    let (_, machine) = run(&format!(r#"
+st 0x{x:X} 100
+st 0x{y:X} 100
+ld 0x{x:X} %1
+ld 0x{y:X} %2
+halt 0
+"#, x = 0xF000 + TEXT_REG_CURSOR_X, y = 0xF000 + TEXT_REG_CURSOR_Y));
+    let r = machine.registers(0).unwrap();
+    assert_eq!((r[1], r[2]), (3, 1));
+}
@@ -0,0 +1,341 @@
+mod common;
+
+use crystalvm::*;
+use common::{run, map_pages};
+
+#[test]
+fn permission_registers_of_running_children_are_fixed() {
+    let (status, _) = run(&format!(r#"
+fork 0x7000 %1
+cmp %1 0
+jz child
+tch_modpr %1 {max} 0xF000
+mov %F %2
+mov 0 %F
+tch_start %1
+tch_modpr %1 {max} 0xE000
+mov %F %3
+mov 0 %F
+tch_getpr %1 {max} %4
+tch_modpr 0 {max} 0xF000
+mov %F %5
+tch_getpr 0 {max} %6
+t_join %1 %7
+halt 0
+child:
+halt 0
+"#, max = PR_ACCESS_MAX));
+    let r = status.registers;
+    assert_eq!(r[2] & FLAG_BIT_E, 0);
+    assert_ne!(r[3] & FLAG_BIT_E, 0);
+    assert_eq!(r[4], 0xF000);
+    // a running thread can still narrow itself
+    assert_eq!(r[5] & FLAG_BIT_E, 0);
+    assert_eq!(r[6], 0xF000);
+}
+
+#[test]
+fn joined_children_are_removed() {
+    let (machine, _) = common::machine(r#"
+fork 0x7000 %1
+cmp %1 0
+jz child
+fork 0x7800 %2
+cmp %2 0
+jz child
+tch_start %1
+tch_start %2
+t_join %1 %3
+t_join %1 %4
+mov %F %5
+wait:
+t_state %2 %6
+cmp %6 3
+jnz wait
+halt 0
+child:
+halt 5
+"#);
+    let status = machine.run().unwrap();
+    assert_eq!(status.registers[3], 5);
+    // the id of a joined child is invalid
+    assert_ne!(status.registers[5] & FLAG_BIT_E, 0);
+    assert_eq!(machine.thread_ids(), vec![0, 2]);
+    assert_eq!(machine.exit_status(2).unwrap().unwrap().code, 5);
+}
+
+#[test]
+fn faults_of_children_are_recorded() {
+    for scheduler in [Scheduler::Threaded, Scheduler::Deterministic { time_slice: 10, seed: 0 }] {
+        // the page fault can not be delivered on the unmapped stack of the child, which crashes it
+        let (machine, _) = common::machine(&format!(r#"
+{}
+mov 0x8000 %P
+fork 0x7000 %1
+cmp %1 0
+jz child
+tch_start %1
+wait:
+t_state %1 %2
+cmp %2 3
+jnz wait
+halt 0
+child:
+mov vectors %V
+mov 1 %M
+mov 0x5000 %S
+ei
+ld 0x5000 %1
+halt 1
+handler:
+reti
+vectors:
+.u32 handler
+"#, map_pages(&[(5, 0)])));
+        let machine = machine.with_scheduler(scheduler);
+        assert_eq!(machine.run().unwrap().reason, ExitReason::Halted);
+        assert_eq!(machine.exit_status(1).unwrap().unwrap().reason, ExitReason::Faulted);
+        assert!(matches!(machine.guest_fault(1).unwrap(), Some(VmError::GuestFault { thread_id: 1, .. })));
+    }
+}
+
+#[test]
+fn fault_of_main_thread_fails_run() {
+    for scheduler in [Scheduler::Threaded, Scheduler::Deterministic { time_slice: 10, seed: 0 }] {
+        let (machine, _) = common::machine(&format!(r#"
+mov vectors %V
+mov 1 %M
+{}
+mov 0x8000 %P
+mov 0x5000 %S
+ei
+ld 0x5000 %1
+halt 0
+handler:
+reti
+vectors:
+.u32 handler
+"#, map_pages(&[(5, 0)])));
+        let machine = machine.with_scheduler(scheduler);
+        assert!(matches!(machine.run(), Err(VmError::GuestFault { thread_id: 0, .. })));
+        assert_eq!(machine.exit_status(0).unwrap().unwrap().reason, ExitReason::Faulted);
+    }
+}
+
+#[test]
+fn waiting_threads_skip_to_the_next_timer() {
+    let (machine, _) = common::machine(&format!(r#"
+mov vectors %V
+mov 1 %M
+st 0xF004 0x40000000
+st 0xF014 0x80000000
+st 0xF000 {enable}
+ei
+wfi
+halt 1
+handler:
+reti
+vectors:
+.u32 handler
+"#, enable = TIMER_CTRL_ENABLE));
+    let machine = machine.with_mmio_device(0x10, 0xF000..0xF000 + TIMER_REGS_SIZE, Box::new(Timer::new())).unwrap()
+        .with_scheduler(Scheduler::Deterministic { time_slice: 10, seed: 0 });
+    assert_eq!(machine.run().unwrap().code, 1);
+    assert!(machine.ticks() >= 0x40000000);
+}
+
+#[test]
+fn interrupts_of_the_last_tick_wake_waiting_threads() {
+    // the transfer completes while the thread waits, in the last tick of a round
+    let (machine, _) = common::machine(&format!(r#"
+mov vectors %V
+mov 1 %M
+st 0x5000 {mem_to_mem}
+st 0x5004 0x4000
+st 0x5008 0x4100
+st 0x500C 16
+st 0x5014 0x80000000
+dma 0x5000
+ei
+wfi
+halt 1
+handler:
+reti
+vectors:
+.u32 handler
+"#, mem_to_mem = DMA_MEM_TO_MEM));
+    let machine = machine.with_dma_rate(1).with_scheduler(Scheduler::Deterministic { time_slice: 10, seed: 0 });
+    assert_eq!(machine.run().unwrap().code, 1);
+}
+
+#[test]
+fn waiting_without_wakeup_is_a_deadlock() {
+    let (machine, _) = common::machine(r#"
+fork 0x7000 %1
+cmp %1 0
+jz child
+tch_start %1
+t_join %1 %2
+halt 0
+child:
+sig_wait %1
+halt 1
+"#);
+    let machine = machine.with_scheduler(Scheduler::Deterministic { time_slice: 10, seed: 0 });
+    assert!(matches!(machine.run(), Err(VmError::Deadlock)));
+}
+
+#[test]
+fn fork_rejects_child_ids_the_child_can_not_see() {
+    let (machine, _) = common::machine(r#"
+mov stack %S
+mov stack %B
+fork 0x7000 %S
+mov %F %1
+mov 0 %F
+fork 0x7000 %B
+mov %F %2
+mov 0 %F
+fork 0x7000 *
+mov %F %3
+mov %S %4
+mov %B %5
+halt 0
+@0x1000
+stack:
+"#);
+    let status = machine.run().unwrap();
+    // no child was created
+    assert_eq!(machine.thread_ids(), vec![0]);
+    for r in 1..=3 {
+        assert_ne!(status.registers[r] & FLAG_BIT_E, 0);
+    }
+    assert_eq!(status.registers[4], 0x1000);
+    assert_eq!(status.registers[5], 0x1000);
+}
+
+#[test]
+fn fork_copies_registers_with_a_new_stack() {
+    let (machine, _) = common::machine(r#"
+mov stack %S
+mov stack %B
+mov 0x55 *
+mov 7 %5
+fork 0x6000 %1
+cmp %1 0
+jz child
+mov 8 %5
+tch_start %1
+t_join %1 %2
+mov * %6
+halt 0
+child:
+st 0x2000 %5
+st 0x2004 %S
+st 0x2008 %B
+st 0x200C %1
+halt 0
+@0x1000
+stack:
+"#);
+    let status = machine.run().unwrap();
+    let r = status.registers;
+    assert_eq!(r[1], 1);
+    // the parent keeps its stack and registers
+    assert_eq!(r[5], 8);
+    assert_eq!(r[6], 0x55);
+    assert_eq!(machine.read_u32(0x2000).unwrap(), 7);
+    assert_eq!(machine.read_u32(0x2004).unwrap(), 0x6000);
+    assert_eq!(machine.read_u32(0x2008).unwrap(), 0x6000);
+    assert_eq!(machine.read_u32(0x200C).unwrap(), 0);
+}
+
+#[test]
+fn spawn_starts_with_cleared_registers() {
+    let (machine, _) = common::machine(r#"
+mov 7 %5
+spawn child 0x6000 %1
+tch_start %1
+t_join %1 %2
+halt 0
+child:
+st 0x2000 %S
+st 0x2004 %B
+halt %5
+"#);
+    let status = machine.run().unwrap();
+    assert_eq!(status.registers[2], 0);
+    assert_eq!(machine.read_u32(0x2000).unwrap(), 0x6000);
+    assert_eq!(machine.read_u32(0x2004).unwrap(), 0x6000);
+}
+
+#[test]
+fn killed_children_exit_with_0() {
+    let (status, _) = run(r#"
+fork 0x7000 %1
+cmp %1 0
+jz child
+t_state %1 %2
+t_kill %1
+t_state %1 %3
+t_join %1 %4
+fork 0x7800 %5
+cmp %5 0
+jz child
+tch_start %1
+mov %F %6
+tch_start %5
+t_kill %5
+t_join %5 %7
+t_state %5 %8
+mov %F %9
+halt 0
+child:
+jmp child
+"#);
+    let r = status.registers;
+    // a ready child is terminated right away and can not be started anymore
+    assert_eq!((r[2], r[3], r[4]), (0, 3, 0));
+    assert_ne!(r[6] & FLAG_BIT_E, 0);
+    // a running child stops before its next instruction
+    assert_eq!(r[7], 0);
+    assert_ne!(r[9] & FLAG_BIT_E, 0);
+}
+
+#[test]
+fn only_descendants_can_be_joined_killed_or_inspected() {
+    let (machine, _) = common::machine(r#"
+fork 0x7000 %1
+cmp %1 0
+jz child
+tch_start %1
+t_join %1 %2
+t_state 7 %3
+mov %F %4
+halt 0
+child:
+t_state 0 %3
+st 0x2000 %F
+mov 0 %F
+t_kill 0
+st 0x2004 %F
+mov 0 %F
+t_join 0 %3
+st 0x2008 %F
+mov 0 %F
+t_state 1 %3
+st 0x200C %3
+st 0x2010 %F
+halt 0
+"#);
+    let status = machine.run().unwrap();
+    assert_eq!(status.reason, ExitReason::Halted);
+    assert_ne!(status.registers[4] & FLAG_BIT_E, 0);
+    // the parent is no descendant of the child
+    for addr in [0x2000, 0x2004, 0x2008] {
+        assert_ne!(machine.read_u32(addr).unwrap() & FLAG_BIT_E, 0);
+    }
+    // but the child itself is
+    assert_eq!(machine.read_u32(0x200C).unwrap(), 1);
+    assert_eq!(machine.read_u32(0x2010).unwrap() & FLAG_BIT_E, 0);
+}
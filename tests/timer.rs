@@ -0,0 +1,86 @@
+mod common;
+
+use crystalvm::*;
+
+/// runs src deterministically with a timer mapped at 0xF000, returns the registers of the main thread, the timer and the machine
+fn run(src: &str) -> ([u32;64], Timer, Machine) {
+    let timer = Timer::new();
+    let (machine, _) = common::machine(src);
+    let machine = machine.with_mmio_device(0x10, 0xF000..0xF000 + TIMER_REGS_SIZE, Box::new(timer.clone())).unwrap()
+        .with_scheduler(Scheduler::Deterministic { time_slice: 10, seed: 0 });
+    let status = machine.run().unwrap();
+    (status.registers, timer, machine)
+}
+
+#[test]
+fn one_shot_timer_expires_once() {
+    let (r, timer, machine) = run(&format!(r#"
+st 0xF004 1000
+st 0xF000 {enable}
+ld 0xF00C %1
+wait:
+ld 0xF010 %2
+cmp %2 0
+jz wait
+ld 0xF000 %3
+ld 0xF00C %4
+halt 0
+"#, enable = TIMER_CTRL_ENABLE));
+    assert!(r[1] > 990 && r[1] <= 1000);
+    assert_eq!(r[2], 1);
+    // expiring cleared the enable bit
+    assert_eq!((r[3], r[4]), (0, 0));
+    assert_eq!(timer.pending(), 1);
+    assert!(machine.ticks() >= 1000);
+}
+
+#[test]
+fn periodic_timer_interrupts_until_disabled() {
+    let (r, timer, _) = run(&format!(r#"
+mov vectors %V
+mov 1 %M
+mov stack %S
+st 0xF004 50
+st 0xF014 0x80000000
+st 0xF000 {periodic}
+ei
+wait:
+wfi
+cmp %10 3
+jnz wait
+st 0xF000 0
+ld 0xF010 %1
+st 0xF010 0
+ld 0xF010 %2
+halt 0
+handler:
+add %10 1 %10
+reti
+vectors:
+.u32 handler
+@0x1000
+stack:
+"#, periodic = TIMER_CTRL_ENABLE | TIMER_CTRL_PERIODIC));
+    assert_eq!(r[10], 3);
+    assert_eq!(r[1], 3);
+    // any write acknowledges the expiries
+    assert_eq!(r[2], 0);
+    assert_eq!(timer.pending(), 0);
+}
+
+#[test]
+fn restarting_drops_the_old_deadline() {
+    let (r, _, machine) = run(&format!(r#"
+st 0xF004 100
+st 0xF000 {enable}
+st 0xF004 400
+st 0xF000 {enable}
+wait:
+ld 0xF010 %1
+cmp %1 0
+jz wait
+halt 0
+"#, enable = TIMER_CTRL_ENABLE));
+    assert_eq!(r[1], 1);
+    assert!(machine.ticks() >= 400);
+}